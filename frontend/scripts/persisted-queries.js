@@ -0,0 +1,23 @@
+// Collects the queries in src/gql into a json file of persisted queries,
+// keyed by the sha256 hashes of the queries as urql sends them to /api
+const crypto = require("crypto");
+const fs = require("fs");
+const path = require("path");
+const { parse, print } = require("graphql");
+const { formatDocument } = require("@urql/core");
+
+const dir = path.join(__dirname, "..", "src", "gql");
+const out = process.argv[2] || path.join(__dirname, "..", "..", "persisted-queries.json");
+
+const queries = {};
+for (const file of fs.readdirSync(dir)) {
+  const source = fs.readFileSync(path.join(dir, file), "utf8");
+  for (const [, query] of source.matchAll(/`([^`]*)`/g)) {
+    const printed = print(formatDocument(parse(query)));
+    const hash = crypto.createHash("sha256").update(printed).digest("hex");
+    queries[hash] = printed;
+  }
+}
+
+fs.writeFileSync(out, JSON.stringify(queries, null, 2));
+console.log(`Wrote ${Object.keys(queries).length} queries to ${out}`);
@@ -9,31 +9,86 @@ export type Scalars = {
   Boolean: boolean;
   Int: number;
   Float: number;
+  DateTime: any;
 };
 
 
+export type Adjustment = {
+  __typename?: 'Adjustment';
+  /** Name of the app the asset was edited with, such as "Photos" */
+  editor?: Maybe<Scalars['String']>;
+  /**
+   * Identifier of the format the adjustments are stored in, such as
+   * "com.apple.photo"
+   */
+  format?: Maybe<Scalars['String']>;
+  adjustedAt?: Maybe<Scalars['DateTime']>;
+};
+
 export type Album = {
   __typename?: 'Album';
   id: Scalars['String'];
+  /** Name of the library the album belongs to */
+  library: Scalars['String'];
   title?: Maybe<Scalars['String']>;
   itemsCount: Scalars['Int'];
   photosCount: Scalars['Int'];
   videosCount: Scalars['Int'];
-  createdAt: Scalars['String'];
-  assets: Array<Asset>;
+  createdAt?: Maybe<Scalars['DateTime']>;
+  isSmart: Scalars['Boolean'];
+  /** Whether this is an iCloud shared album */
+  isShared: Scalars['Boolean'];
+  /** The folder this album is in, unless it's not accessible */
+  folder?: Maybe<Folder>;
+  assets: AssetConnection;
+  /** Where the assets in this album were taken, most common places first */
+  places: Array<PlaceCount>;
   keyAssets: Array<Asset>;
 };
 
 
 export type AlbumAssetsArgs = {
-  offset: Scalars['Int'];
-  limit: Scalars['Int'];
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+  filter?: Maybe<AssetFilter>;
+};
+
+export type AlbumConnection = {
+  __typename?: 'AlbumConnection';
+  /** Information to aid in pagination. */
+  pageInfo: PageInfo;
+  /** A list of edges. */
+  edges?: Maybe<Array<Maybe<AlbumEdge>>>;
+  totalCount: Scalars['Int'];
+};
+
+/** An edge in a connection. */
+export type AlbumEdge = {
+  __typename?: 'AlbumEdge';
+  /** The item at the end of the edge */
+  node: Album;
+  /** A cursor for use in pagination */
+  cursor: Scalars['String'];
 };
 
 export type Asset = {
   __typename?: 'Asset';
   id: Scalars['String'];
-  createdAt: Scalars['String'];
+  /** Name of the library the asset belongs to */
+  library: Scalars['String'];
+  /**
+   * When the asset was captured, in the timezone it was captured in.
+   * In UTC when Photos doesn't know that timezone
+   */
+  createdAt?: Maybe<Scalars['DateTime']>;
+  createdAtUtc?: Maybe<Scalars['DateTime']>;
+  /**
+   * Offset of the timezone the asset was captured in, in seconds east
+   * of UTC. Null when Photos doesn't know it
+   */
+  timezoneOffset?: Maybe<Scalars['Int']>;
   height: Scalars['Int'];
   width: Scalars['Int'];
   latitude: Scalars['Float'];
@@ -41,6 +96,105 @@ export type Asset = {
   duration: Scalars['Float'];
   isVideo: Scalars['Boolean'];
   entity?: Maybe<Entity>;
+  title?: Maybe<Scalars['String']>;
+  caption?: Maybe<Scalars['String']>;
+  originalFilename?: Maybe<Scalars['String']>;
+  /** Size of the original file in bytes */
+  fileSize?: Maybe<Scalars['Int']>;
+  /** Whether the asset has been edited since it was imported */
+  isEdited: Scalars['Boolean'];
+  /** The latest edit made to the asset */
+  adjustment?: Maybe<Adjustment>;
+  exif?: Maybe<Exif>;
+  /** Where the asset was taken, as reverse geocoded by Photos */
+  place?: Maybe<Place>;
+  /** Keywords the asset is tagged with in Photos */
+  keywords: Array<Keyword>;
+  /** Faces of the recognised people on this asset */
+  people: Array<Face>;
+};
+
+export type AssetConnection = {
+  __typename?: 'AssetConnection';
+  /** Information to aid in pagination. */
+  pageInfo: PageInfo;
+  /** A list of edges. */
+  edges?: Maybe<Array<Maybe<AssetEdge>>>;
+  totalCount: Scalars['Int'];
+};
+
+/** An edge in a connection. */
+export type AssetEdge = {
+  __typename?: 'AssetEdge';
+  /** The item at the end of the edge */
+  node: Asset;
+  /** A cursor for use in pagination */
+  cursor: Scalars['String'];
+};
+
+/** Narrows down the assets returned for an album */
+export type AssetFilter = {
+  /** Only return assets marked as favorite in Photos */
+  favorites: Scalars['Boolean'];
+  mediaType?: Maybe<MediaType>;
+  excludeScreenshots: Scalars['Boolean'];
+  subtype?: Maybe<MediaSubtype>;
+};
+
+/**
+ * The visible area of a map. `west` is greater than `east` when the area
+ * crosses the antimeridian
+ */
+export type BoundingBox = {
+  south: Scalars['Float'];
+  west: Scalars['Float'];
+  north: Scalars['Float'];
+  east: Scalars['Float'];
+};
+
+export type Bucket = {
+  __typename?: 'Bucket';
+  /** The date of the bucket formatted as YYYY-MM-DD, YYYY-MM or YYYY */
+  key: Scalars['String'];
+  count: Scalars['Int'];
+  startsAt?: Maybe<Scalars['DateTime']>;
+  endsAt?: Maybe<Scalars['DateTime']>;
+  /** The moments Photos has grouped the assets of this bucket in */
+  moments: Array<Moment>;
+  assets: AssetConnection;
+};
+
+
+export type BucketAssetsArgs = {
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+  filter?: Maybe<AssetFilter>;
+};
+
+export type Cluster = {
+  __typename?: 'Cluster';
+  count: Scalars['Int'];
+  /** Latitude of the centroid of the clustered assets */
+  latitude: Scalars['Float'];
+  /** Longitude of the centroid of the clustered assets */
+  longitude: Scalars['Float'];
+  /** An asset representing the cluster */
+  asset?: Maybe<Asset>;
+};
+
+export type DateRange = {
+  __typename?: 'DateRange';
+  from: Scalars['String'];
+  to: Scalars['String'];
+};
+
+export type DateRangeInput = {
+  /** First day of the range, formatted as YYYY-MM-DD */
+  from: Scalars['String'];
+  /** Last day of the range, formatted as YYYY-MM-DD */
+  to: Scalars['String'];
 };
 
 export type Entity = {
@@ -51,6 +205,125 @@ export type Entity = {
   parent?: Maybe<Entity>;
 };
 
+export type Exif = {
+  __typename?: 'Exif';
+  orientation?: Maybe<Scalars['Int']>;
+  cameraMake?: Maybe<Scalars['String']>;
+  cameraModel?: Maybe<Scalars['String']>;
+  lensModel?: Maybe<Scalars['String']>;
+  focalLength?: Maybe<Scalars['Float']>;
+  focalLengthIn35Mm?: Maybe<Scalars['Int']>;
+  aperture?: Maybe<Scalars['Float']>;
+  iso?: Maybe<Scalars['Int']>;
+  /** Exposure time in seconds */
+  shutterSpeed?: Maybe<Scalars['Float']>;
+};
+
+export type Face = {
+  __typename?: 'Face';
+  centerX: Scalars['Float'];
+  centerY: Scalars['Float'];
+  size: Scalars['Float'];
+  person?: Maybe<Person>;
+  asset?: Maybe<Asset>;
+};
+
+export type Folder = {
+  __typename?: 'Folder';
+  id: Scalars['String'];
+  /** Name of the library the folder belongs to */
+  library: Scalars['String'];
+  title?: Maybe<Scalars['String']>;
+  /** The parent folder, unless it's not accessible */
+  parent?: Maybe<Folder>;
+  children: Array<Folder>;
+  albums: AlbumConnection;
+};
+
+
+export type FolderAlbumsArgs = {
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+};
+
+export enum Granularity {
+  Day = 'DAY',
+  Month = 'MONTH',
+  Year = 'YEAR'
+}
+
+export type Keyword = {
+  __typename?: 'Keyword';
+  id: Scalars['String'];
+  /** Name of the library the keyword belongs to */
+  library: Scalars['String'];
+  title: Scalars['String'];
+  assetCount: Scalars['Int'];
+  assets: AssetConnection;
+};
+
+
+export type KeywordAssetsArgs = {
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+  filter?: Maybe<AssetFilter>;
+};
+
+export enum MediaSubtype {
+  Panorama = 'PANORAMA',
+  LivePhoto = 'LIVE_PHOTO',
+  Portrait = 'PORTRAIT',
+  Screenshot = 'SCREENSHOT',
+  SloMo = 'SLO_MO',
+  TimeLapse = 'TIME_LAPSE'
+}
+
+export enum MediaType {
+  Photo = 'PHOTO',
+  Video = 'VIDEO'
+}
+
+export type Memory = {
+  __typename?: 'Memory';
+  id: Scalars['String'];
+  /** Name of the library the memory belongs to */
+  library: Scalars['String'];
+  title?: Maybe<Scalars['String']>;
+  subtitle?: Maybe<Scalars['String']>;
+  createdAt?: Maybe<Scalars['DateTime']>;
+  favorite: Scalars['Boolean'];
+  /** The asset Photos uses as the cover of this memory */
+  keyAsset?: Maybe<Asset>;
+  /** The assets Photos picked for this memory */
+  assets: AssetConnection;
+};
+
+
+export type MemoryAssetsArgs = {
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+  filter?: Maybe<AssetFilter>;
+};
+
+export type Moment = {
+  __typename?: 'Moment';
+  id: Scalars['String'];
+  /** Name of the library the moment belongs to */
+  library: Scalars['String'];
+  title?: Maybe<Scalars['String']>;
+  subtitle?: Maybe<Scalars['String']>;
+  latitude: Scalars['Float'];
+  longitude: Scalars['Float'];
+  startsAt?: Maybe<Scalars['DateTime']>;
+  endsAt?: Maybe<Scalars['DateTime']>;
+};
+
 export type MutationRoot = {
   __typename?: 'MutationRoot';
   createToken?: Maybe<Token>;
@@ -74,11 +347,126 @@ export type MutationRootDeleteTokenArgs = {
   id: Scalars['String'];
 };
 
+/** Information about pagination in a connection */
+export type PageInfo = {
+  __typename?: 'PageInfo';
+  /** When paginating backwards, are there more items? */
+  hasPreviousPage: Scalars['Boolean'];
+  /** When paginating forwards, are there more items? */
+  hasNextPage: Scalars['Boolean'];
+  /** When paginating backwards, the cursor to continue. */
+  startCursor?: Maybe<Scalars['String']>;
+  /** When paginating forwards, the cursor to continue. */
+  endCursor?: Maybe<Scalars['String']>;
+};
+
+export enum Permission {
+  Browse = 'BROWSE',
+  BrowseAll = 'BROWSE_ALL',
+  ShareAlbums = 'SHARE_ALBUMS',
+  ManageTokens = 'MANAGE_TOKENS',
+  Upload = 'UPLOAD'
+}
+
+export type Person = {
+  __typename?: 'Person';
+  id: Scalars['String'];
+  /** Name of the library the person belongs to */
+  library: Scalars['String'];
+  name: Scalars['String'];
+  displayName?: Maybe<Scalars['String']>;
+  faceCount: Scalars['Int'];
+  /** The face Photos uses to represent this person */
+  keyFace?: Maybe<Face>;
+  assets: AssetConnection;
+};
+
+
+export type PersonAssetsArgs = {
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+  filter?: Maybe<AssetFilter>;
+};
+
+export type Place = {
+  __typename?: 'Place';
+  /** A short description of the place, such as "Lisbon, Portugal" */
+  name?: Maybe<Scalars['String']>;
+  street?: Maybe<Scalars['String']>;
+  city?: Maybe<Scalars['String']>;
+  state?: Maybe<Scalars['String']>;
+  country?: Maybe<Scalars['String']>;
+  /** ISO 3166 code of the country */
+  countryCode?: Maybe<Scalars['String']>;
+  /**
+   * Names of the areas and points of interest around the place,
+   * from the most to the least specific
+   */
+  names: Array<Scalars['String']>;
+};
+
+export type PlaceCount = {
+  __typename?: 'PlaceCount';
+  name: Scalars['String'];
+  count: Scalars['Int'];
+};
+
 export type QueryRoot = {
   __typename?: 'QueryRoot';
+  /** Get an album by its id */
   album?: Maybe<Album>;
-  myAlbums: Array<Album>;
+  /** "My Albums" which have been xpozed, keeping the original Photos sorting */
+  myAlbums: AlbumConnection;
+  /**
+   * iCloud shared albums which have been xpozed, keeping the original
+   * Photos sorting
+   */
+  sharedAlbums: AlbumConnection;
+  /** Get a folder by its id */
+  folder?: Maybe<Folder>;
+  /** The top level folder of the Photos library */
+  rootFolder?: Maybe<Folder>;
+  /** People recognised by Photos, most photographed first */
+  people: Array<Person>;
+  /** Get a person by their id */
+  person?: Maybe<Person>;
+  /** Keywords assigned to assets in Photos, sorted by title */
+  keywords: Array<Keyword>;
+  /** Get a keyword by its id */
+  keyword?: Maybe<Keyword>;
+  /**
+   * The whole library grouped by capture date, newest first. Only
+   * available to owners and tokens with access to date ranges
+   */
+  timeline: Array<Bucket>;
+  /** Assets within a bounding box, clustered for the given map zoom level */
+  places: Array<Cluster>;
+  /** Assets taken within `radius` meters of a location */
+  assetsNear: AssetConnection;
+  /**
+   * Albums and assets of all libraries with titles, captions, keywords,
+   * people or places matching the search query, best matches first
+   */
+  search: SearchHitConnection;
+  /** Names of the Photos libraries being served, the default one first */
+  libraries: Array<Scalars['String']>;
+  /** Returns the current access token */
   me: Token;
+  /** Memories generated by Photos, newest first */
+  memories: Array<Memory>;
+  /** Get a memory by its id */
+  memory?: Maybe<Memory>;
+  /**
+   * Assets taken on this day of the year in previous years. `date` is
+   * formatted as YYYY-MM-DD and defaults to today
+   */
+  onThisDay: AssetConnection;
+  /**
+   * Returns the available access tokens. Curators only get the ones
+   * they've created
+   */
   tokens: Array<Token>;
 };
 
@@ -89,23 +477,179 @@ export type QueryRootAlbumArgs = {
 
 
 export type QueryRootMyAlbumsArgs = {
-  page?: Maybe<Scalars['Int']>;
+  library?: Maybe<Scalars['String']>;
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
 };
 
+
+export type QueryRootSharedAlbumsArgs = {
+  library?: Maybe<Scalars['String']>;
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+};
+
+
+export type QueryRootFolderArgs = {
+  id: Scalars['String'];
+};
+
+
+export type QueryRootRootFolderArgs = {
+  library?: Maybe<Scalars['String']>;
+};
+
+
+export type QueryRootPeopleArgs = {
+  library?: Maybe<Scalars['String']>;
+};
+
+
+export type QueryRootPersonArgs = {
+  id: Scalars['String'];
+};
+
+
+export type QueryRootKeywordsArgs = {
+  library?: Maybe<Scalars['String']>;
+};
+
+
+export type QueryRootKeywordArgs = {
+  id: Scalars['String'];
+};
+
+
+export type QueryRootTimelineArgs = {
+  library?: Maybe<Scalars['String']>;
+  granularity: Granularity;
+};
+
+
+export type QueryRootPlacesArgs = {
+  library?: Maybe<Scalars['String']>;
+  bbox: BoundingBox;
+  zoom: Scalars['Int'];
+};
+
+
+export type QueryRootAssetsNearArgs = {
+  library?: Maybe<Scalars['String']>;
+  latitude: Scalars['Float'];
+  longitude: Scalars['Float'];
+  radius: Scalars['Float'];
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+  filter?: Maybe<AssetFilter>;
+};
+
+
+export type QueryRootSearchArgs = {
+  query: Scalars['String'];
+  after?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+};
+
+
+export type QueryRootMemoriesArgs = {
+  library?: Maybe<Scalars['String']>;
+  favorites: Scalars['Boolean'];
+  limit: Scalars['Int'];
+};
+
+
+export type QueryRootMemoryArgs = {
+  id: Scalars['String'];
+};
+
+
+export type QueryRootOnThisDayArgs = {
+  library?: Maybe<Scalars['String']>;
+  date?: Maybe<Scalars['String']>;
+  after?: Maybe<Scalars['String']>;
+  before?: Maybe<Scalars['String']>;
+  first?: Maybe<Scalars['Int']>;
+  last?: Maybe<Scalars['Int']>;
+  filter?: Maybe<AssetFilter>;
+};
+
+/** What a token is used for. Each role comes with a fixed set of permissions */
+export enum Role {
+  Owner = 'OWNER',
+  Curator = 'CURATOR',
+  Viewer = 'VIEWER',
+  Uploader = 'UPLOADER'
+}
+
+export type SearchHit = {
+  __typename?: 'SearchHit';
+  kind: SearchKind;
+  id: Scalars['String'];
+  /** The title of the match, with the matching terms wrapped in <mark> */
+  title: Scalars['String'];
+  /**
+   * The best matching fragment of the indexed text, with the matching
+   * terms wrapped in <mark>
+   */
+  snippet: Scalars['String'];
+  album?: Maybe<Album>;
+  asset?: Maybe<Asset>;
+};
+
+export type SearchHitConnection = {
+  __typename?: 'SearchHitConnection';
+  /** Information to aid in pagination. */
+  pageInfo: PageInfo;
+  /** A list of edges. */
+  edges?: Maybe<Array<Maybe<SearchHitEdge>>>;
+  totalCount: Scalars['Int'];
+};
+
+/** An edge in a connection. */
+export type SearchHitEdge = {
+  __typename?: 'SearchHitEdge';
+  /** The item at the end of the edge */
+  node: SearchHit;
+  /** A cursor for use in pagination */
+  cursor: Scalars['String'];
+};
+
+export enum SearchKind {
+  Album = 'ALBUM',
+  Asset = 'ASSET'
+}
+
 export type Token = {
   __typename?: 'Token';
   id: Scalars['String'];
   name: Scalars['String'];
   sessionBound: Scalars['Boolean'];
+  role: Role;
+  permissions: Array<Permission>;
+  /** @deprecated Use role or permissions instead */
   admin: Scalars['Boolean'];
   sessionId?: Maybe<Scalars['String']>;
   createdAt: Scalars['String'];
   whitelistedAlbums?: Maybe<Array<Album>>;
+  whitelistedFolderIds?: Maybe<Array<Scalars['String']>>;
+  whitelistedPersonIds?: Maybe<Array<Scalars['String']>>;
+  whitelistedKeywordIds?: Maybe<Array<Scalars['String']>>;
+  whitelistedDateRanges?: Maybe<Array<DateRange>>;
 };
 
 export type TokenInput = {
   name: Scalars['String'];
   sessionBound: Scalars['Boolean'];
-  admin: Scalars['Boolean'];
+  role: Role;
   albumIds?: Maybe<Array<Scalars['String']>>;
+  folderIds?: Maybe<Array<Scalars['String']>>;
+  personIds?: Maybe<Array<Scalars['String']>>;
+  keywordIds?: Maybe<Array<Scalars['String']>>;
+  dateRanges?: Maybe<Array<DateRangeInput>>;
 };
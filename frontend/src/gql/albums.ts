@@ -1,41 +1,65 @@
 export const getMyAlbums = `
-  query($page: Int!) {
-    myAlbums(page: $page) {
-      id
-      title
-      photosCount
-      videosCount
-      createdAt
-      keyAssets {
-        id
+  query($first: Int!, $after: String) {
+    myAlbums(first: $first, after: $after) {
+      pageInfo {
+        hasNextPage
+        endCursor
+      }
+      edges {
+        node {
+          id
+          title
+          photosCount
+          videosCount
+          createdAt
+          keyAssets {
+            id
+          }
+        }
       }
     }
   }
 `;
 
 export const getMyAlbumsForAccess = `
-  query {
-    myAlbums {
-      id
-      title
+  query($after: String) {
+    myAlbums(first: 100, after: $after) {
+      pageInfo {
+        hasNextPage
+        endCursor
+      }
+      edges {
+        node {
+          id
+          title
+        }
+      }
     }
   }
 `;
 
 export const getAlbum = `
-  query($id: String!, $offset: Int!, $limit: Int!) {
+  query($id: String!, $first: Int!, $after: String) {
     album(id: $id) {
       id
       title
       photosCount
       videosCount
-      assets(offset: $offset, limit: $limit) {
-        id
-        width
-        height
-        isVideo
-        duration
-        createdAt
+      assets(first: $first, after: $after) {
+        pageInfo {
+          hasNextPage
+          endCursor
+        }
+        edges {
+          node {
+            id
+            width
+            height
+            isVideo
+            duration
+            createdAt
+          }
+        }
       }
     }
   }
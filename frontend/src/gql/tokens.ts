@@ -6,7 +6,7 @@ export const getTokens = `
       name
       sessionId
       sessionBound
-      admin
+      role
       createdAt
       whitelistedAlbums {
         id
@@ -20,7 +20,8 @@ export const me = `
     me {
       id
       name
-      admin
+      role
+      permissions
     }
   }
 `
@@ -33,7 +34,7 @@ export const createToken = `
       name
       sessionId
       sessionBound
-      admin
+      role
       createdAt
       whitelistedAlbums {
         id
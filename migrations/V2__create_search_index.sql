@@ -0,0 +1,18 @@
+CREATE VIRTUAL TABLE "search_index" USING fts5(
+  "library" UNINDEXED,
+  "kind" UNINDEXED,
+  "uuid" UNINDEXED,
+  "title",
+  "caption",
+  "keywords",
+  "people",
+  "places",
+  tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TABLE "search_index_state" (
+  "library" varchar NOT NULL,
+  "kind" varchar NOT NULL,
+  "modified_at" real NOT NULL DEFAULT 0,
+  PRIMARY KEY ("library", "kind")
+);
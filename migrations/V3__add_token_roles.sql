@@ -0,0 +1,21 @@
+CREATE TABLE "tokens_with_roles" (
+  "name" varchar NOT NULL,
+  "session_bound" integer NOT NULL DEFAULT 1,
+  "role" varchar NOT NULL DEFAULT 'viewer' CHECK ("role" IN ('owner', 'curator', 'viewer', 'uploader')),
+  "session_id" varchar NULL,
+  "token" varchar NOT NULL,
+  "whitelist" text NULL,
+  "created_by" varchar NULL,
+  "created_at" datetime NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO "tokens_with_roles" ("name", "session_bound", "role", "session_id", "token", "whitelist", "created_at")
+SELECT "name", "session_bound", CASE WHEN "admin" = 1 THEN 'owner' ELSE 'viewer' END, "session_id", "token", "whitelist", "created_at"
+FROM "tokens";
+
+DROP TABLE "tokens";
+ALTER TABLE "tokens_with_roles" RENAME TO "tokens";
+
+CREATE UNIQUE INDEX "token" ON "tokens" ("token");
+CREATE INDEX "session_id" ON "tokens" ("session_id");
+CREATE INDEX "created_by" ON "tokens" ("created_by");
@@ -79,7 +79,7 @@ where
                 let _ = session.set("id", id);
             }
 
-            let token = authenticate(&req.path(), &session, &dbs.app).await;
+            let token = authenticate(req.path(), &session, &dbs.app).await;
 
             if let Some(a) = token {
                 req.head().extensions_mut().insert(a);
@@ -92,11 +92,7 @@ where
     }
 }
 
-const NO_AUTH_PATHS: [&str; 3] = [
-    "/auth",
-    "/robots.txt",
-    "/share.html"
-];
+const NO_AUTH_PATHS: [&str; 3] = ["/auth", "/robots.txt", "/share.html"];
 
 async fn authenticate(path: &str, session: &Session, pool: &SqlitePool) -> Option<Token> {
     if NO_AUTH_PATHS.contains(&path) {
@@ -122,7 +118,7 @@ async fn authenticate_user(session: &Session, pool: &SqlitePool) -> Option<Token
 pub async fn auth(session: Session, req: HttpRequest) -> AWResult<HttpResponse> {
     let token = req.query_string();
 
-    if token != "" {
+    if !token.is_empty() {
         let _ = session.set("token", token);
     }
 
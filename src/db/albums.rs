@@ -1,89 +1,236 @@
 use super::{
-    assets::{assets, assets_by_id, Asset},
-    Databases, Entity,
+    assets::{album_join_tables, assets, assets_matching, visible_assets, Asset, AssetFilter},
+    dates::Timestamp,
+    errors::graphql_error,
+    folders::{folder_by_id, Folder},
+    libraries::{library, Library},
+    loaders::loaders,
+    pagination::{Page, PagedConnection, SortCursor, Sorted},
+    places::{place_counts, PlaceCount},
+    smart_albums::{SmartQuery, SMART_ALBUM_KIND},
+    tokens::Token,
+    whitelist::{is_uuid, Whitelist},
+    Entity,
 };
 use anyhow::Result;
-use async_graphql::{Context, Object, Result as AGResult};
+use async_graphql::{connection::query, dataloader::Loader, Context, Object, Result as AGResult};
 use sql_builder::prelude::*;
-use sqlx::{query_as, sqlite::SqlitePool};
+use sqlx::query_as;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-pub type AllowedAlbumIds = Option<Vec<String>>;
+pub type AllowedAlbumIds = Option<Whitelist>;
 
-#[derive(sqlx::FromRow)]
+const SORT_KEY: &str = "IFNULL(Z_FOK_PARENTFOLDER, 0)";
+
+/// ZKIND of the iCloud shared albums the user owns or is subscribed to
+pub const SHARED_ALBUM_KIND: i32 = 1505;
+
+#[derive(sqlx::FromRow, Clone)]
 pub struct Album {
     pub id: i32,
     uuid: String,
+    library: String,
     title: Option<String>,
     items_count: i32,
     photos_count: i32,
     videos_count: i32,
-    created_at: String,
+    created_at: f64,
     key_asset_id: Option<i32>,
     secondary_key_asset_id: Option<i32>,
     tertiery_key_asset_id: Option<i32>,
     custom_key_asset_id: Option<i32>,
+    folder_id: Option<i32>,
+    kind: i32,
+    query_parameters: Option<Vec<u8>>,
+    sort_key: i64,
+}
+
+#[derive(sqlx::FromRow, Clone, Default)]
+pub struct AlbumCounts {
+    album_id: i32,
+    items_count: i32,
+    photos_count: i32,
+    videos_count: i32,
+}
+
+impl Album {
+    /// The sql conditions selecting the assets of a smart album
+    fn smart_conditions(&self, library: &Library) -> Result<Option<String>> {
+        if self.kind != SMART_ALBUM_KIND {
+            return Ok(None);
+        }
+
+        match &self.query_parameters {
+            Some(blob) => {
+                let query = SmartQuery::parse(blob)?;
+                Ok(Some(query.to_sql(&library.entities(), &library.model())?))
+            }
+            None => Ok(Some("0".to_string())),
+        }
+    }
+
+    /// Sql conditions on the `assets` table matching the assets in this album
+    fn asset_conditions(&self, library: &Library) -> Result<String> {
+        if let Some(conditions) = self.smart_conditions(library)? {
+            return Ok(conditions);
+        }
+
+        let joins = album_join_tables(&library.entities())?;
+        Ok(format!(
+            "assets.Z_PK IN (SELECT joins.{} FROM {} WHERE joins.{} = {})",
+            joins.2, joins.0, joins.1, self.id
+        ))
+    }
+
+    /// The number of items, photos and videos in the album. Photos doesn't
+    /// cache these for smart albums, so they are counted instead, batched
+    /// with all other smart albums resolved in the same request
+    async fn counts(&self, ctx: &Context<'_>) -> AGResult<AlbumCounts> {
+        if self.kind != SMART_ALBUM_KIND {
+            return Ok(AlbumCounts {
+                album_id: self.id,
+                items_count: self.items_count,
+                photos_count: self.photos_count,
+                videos_count: self.videos_count,
+            });
+        }
+
+        let loader = &loaders(ctx, &self.library)?.album_counts;
+        Ok(loader
+            .load_one(self.id)
+            .await
+            .map_err(graphql_error)?
+            .unwrap_or_default())
+    }
+}
+
+impl Sorted for Album {
+    fn cursor(&self) -> SortCursor {
+        SortCursor {
+            key: self.sort_key,
+            id: self.id,
+        }
+    }
 }
 
 #[Object]
 impl Album {
-    async fn id(&self) -> &String {
-        &self.uuid
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
+    }
+    /// Name of the library the album belongs to
+    async fn library(&self) -> &String {
+        &self.library
     }
     async fn title(&self) -> &Option<String> {
         &self.title
     }
-    async fn items_count(&self) -> &i32 {
-        &self.items_count
+    async fn items_count(&self, ctx: &Context<'_>) -> AGResult<i32> {
+        Ok(self.counts(ctx).await?.items_count)
+    }
+    async fn photos_count(&self, ctx: &Context<'_>) -> AGResult<i32> {
+        Ok(self.counts(ctx).await?.photos_count)
+    }
+    async fn videos_count(&self, ctx: &Context<'_>) -> AGResult<i32> {
+        Ok(self.counts(ctx).await?.videos_count)
     }
-    async fn photos_count(&self) -> &i32 {
-        &self.photos_count
+    async fn created_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.created_at)
     }
-    async fn videos_count(&self) -> &i32 {
-        &self.videos_count
+    async fn is_smart(&self) -> bool {
+        self.kind == SMART_ALBUM_KIND
     }
-    async fn created_at(&self) -> &String {
-        &self.created_at
+    /// Whether this is an iCloud shared album
+    async fn is_shared(&self) -> bool {
+        self.kind == SHARED_ALBUM_KIND
+    }
+    /// The folder this album is in, unless it's not accessible
+    async fn folder(&self, ctx: &Context<'_>) -> AGResult<Option<Folder>> {
+        let library = library(ctx, &self.library)?;
+        match self.folder_id {
+            None => Ok(None),
+            Some(id) => Ok(
+                folder_by_id(library, &ctx.data::<Token>()?.whitelist_for(library), id)
+                    .await
+                    .map_err(graphql_error)?,
+            ),
+        }
     }
 
-    async fn assets(&self, ctx: &Context<'_>, offset: i32, limit: i32) -> AGResult<Vec<Asset>> {
-        let assets = assets(
-            &ctx.data::<Databases>()?.photos,
-            ctx.data::<Vec<Entity>>()?,
-            &self,
-            offset,
-            limit,
+    async fn assets(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        filter: Option<AssetFilter>,
+    ) -> AGResult<PagedConnection<Asset>> {
+        let library = library(ctx, &self.library)?;
+        let filter = filter.unwrap_or_default();
+        let smart_conditions = self.smart_conditions(library).map_err(graphql_error)?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let page = Page::new(after, before, first, last);
+                let result = match &smart_conditions {
+                    Some(conditions) => assets_matching(library, conditions, &filter, &page).await,
+                    None => assets(library, self, &filter, &page).await,
+                };
+                result.map_err(graphql_error)
+            },
         )
-        .await?;
-        Ok(assets)
+        .await
+    }
+
+    /// Where the assets in this album were taken, most common places first
+    async fn places(&self, ctx: &Context<'_>) -> AGResult<Vec<PlaceCount>> {
+        let library = library(ctx, &self.library)?;
+        let conditions = self.asset_conditions(library).map_err(graphql_error)?;
+        Ok(place_counts(library, &conditions)
+            .await
+            .map_err(graphql_error)?)
     }
 
     async fn key_assets(&self, ctx: &Context<'_>) -> AGResult<Vec<Asset>> {
-        let mut ids = vec![];
         let ordered_key_asset_ids = [
             self.custom_key_asset_id,
             self.key_asset_id,
             self.secondary_key_asset_id,
             self.tertiery_key_asset_id,
         ];
+        let ids: Vec<i32> = ordered_key_asset_ids.iter().flatten().copied().collect();
 
-        for id in ordered_key_asset_ids.iter() {
-            if let Some(i) = id {
-                ids.push(*i);
-            }
-        }
-
-        let mut assets = assets_by_id(&ctx.data::<Databases>()?.photos, &ids).await?;
-        assets.sort_by(|a, b| {
-            let a_pos = &ids.iter().position(|&s| s == a.id);
-            let b_pos = &ids.iter().position(|&s| s == b.id);
-            a_pos.unwrap().cmp(&b_pos.unwrap())
-        });
+        let mut assets = loaders(ctx, &self.library)?
+            .assets
+            .load_many(ids.iter().copied())
+            .await
+            .map_err(graphql_error)?;
 
-        Ok(assets)
+        Ok(ids.iter().filter_map(|id| assets.remove(id)).collect())
     }
 }
 
-fn base_select(entity: &Entity, whitelist: &AllowedAlbumIds) -> SqlBuilder {
+/// Sql conditions on ZGENERICALBUM matching the albums created by the
+/// user, smart albums and iCloud shared albums
+pub fn album_kinds(cache: &[Entity]) -> String {
+    let entities: Vec<String> = cache
+        .iter()
+        .filter(|e| e.name == "Album" || e.name == "CloudSharedAlbum")
+        .map(|e| e.id.to_string())
+        .collect();
+    format!(
+        "(Z_ENT IN ({}) OR ZKIND = {})",
+        entities.join(", "),
+        SMART_ALBUM_KIND
+    )
+}
+
+fn base_select(library: &Library, whitelist: &AllowedAlbumIds) -> SqlBuilder {
     let fields = [
         "Z_PK as id",
         "ZUUID as uuid",
@@ -92,67 +239,285 @@ fn base_select(entity: &Entity, whitelist: &AllowedAlbumIds) -> SqlBuilder {
         "ZCACHEDCOUNT as items_count",
         "ZCACHEDPHOTOSCOUNT as photos_count",
         "ZCACHEDVIDEOSCOUNT as videos_count",
-        "datetime(ZCREATIONDATE,'unixepoch','31 years','localtime') as created_at",
+        "ZCREATIONDATE as created_at",
         "ZKEYASSET as key_asset_id",
         "ZSECONDARYKEYASSET as secondary_key_asset_id",
         "ZTERTIARYKEYASSET as tertiery_key_asset_id",
-        "ZCUSTOMKEYASSET as custom_key_asset_id",
+        "ZPARENTFOLDER as folder_id",
+        "ZKIND as kind",
+        "ZCUSTOMQUERYPARAMETERS as query_parameters",
     ];
+    let sort_key = format!("{} as sort_key", SORT_KEY);
 
     let mut builder = SqlBuilder::select_from("ZGENERICALBUM");
     builder
         .fields(&fields)
-        .and_where_eq("Z_ENT", entity.id)
+        .field(format!(
+            "{} as custom_key_asset_id",
+            library.model().custom_key_asset
+        ))
+        .field(library.name_field())
+        .field(sort_key)
+        .and_where(album_kinds(&library.entities()))
         .and_where_is_not_null("ZTITLE")
         .and_where_lt("ZTRASHEDSTATE", 1)
-        .and_where_gt("ZCACHEDCOUNT", 0)
-        .order_asc("Z_FOK_PARENTFOLDER");
+        // smart albums don't keep their counts cached
+        .and_where(format!(
+            "(ZCACHEDCOUNT > 0 OR ZKIND = {})",
+            SMART_ALBUM_KIND
+        ));
 
     if let Some(wl) = whitelist {
-        let allowed_uuids: Vec<String> = wl.iter().map(|v| quote(v)).collect();
-        builder.and_where_in("ZUUID", &allowed_uuids);
+        builder.and_where(wl.album_conditions());
     }
 
     builder
 }
 
 pub async fn album(
-    pool: &SqlitePool,
-    cache: &Vec<Entity>,
+    library: &Library,
     whitelist: &AllowedAlbumIds,
-    uuid: &String,
+    uuid: &str,
 ) -> Result<Option<Album>> {
-    let entity = cache.iter().find(|e| e.name == "Album");
-    let mut select = base_select(
-        entity.expect("Couldn't find an Album entity in the entity cache"),
-        whitelist,
-    );
-    select.and_where("ZUUID = ?".bind(uuid));
+    let mut select = base_select(library, whitelist);
+    select.and_where("ZUUID = ?".bind(&uuid));
 
     let result = query_as::<_, Album>(select.sql()?.as_str())
-        .fetch_optional(pool)
+        .fetch_optional(&library.pool)
         .await?;
 
     Ok(result)
 }
 
+async fn paginate(
+    library: &Library,
+    mut select: SqlBuilder,
+    page: &Page,
+) -> Result<PagedConnection<Album>> {
+    let mut count = select.clone();
+    count.set_field("COUNT(*)");
+    let (total_count,) = query_as::<_, (i64,)>(count.sql()?.as_str())
+        .fetch_one(&library.pool)
+        .await?;
+
+    page.apply(&mut select, SORT_KEY, "Z_PK")?;
+    let records = query_as::<_, Album>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    page.connection(records, total_count)
+}
+
 pub async fn my_albums(
-    pool: &SqlitePool,
-    cache: &Vec<Entity>,
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    page: &Page,
+) -> Result<PagedConnection<Album>> {
+    let mut select = base_select(library, whitelist);
+    select.and_where_ne("ZKIND", SHARED_ALBUM_KIND);
+
+    paginate(library, select, page).await
+}
+
+/// iCloud shared albums, keeping the original Photos sorting
+pub async fn shared_albums(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    page: &Page,
+) -> Result<PagedConnection<Album>> {
+    let mut select = base_select(library, whitelist);
+    select.and_where_eq("ZKIND", SHARED_ALBUM_KIND);
+
+    paginate(library, select, page).await
+}
+
+/// Albums directly inside a folder
+pub async fn folder_albums(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    folder_id: i32,
+    page: &Page,
+) -> Result<PagedConnection<Album>> {
+    let mut select = base_select(library, whitelist);
+    select.and_where_eq("ZPARENTFOLDER", folder_id);
+
+    paginate(library, select, page).await
+}
+
+/// Albums by their uuids, in no particular order
+pub async fn albums_by_uuid(
+    library: &Library,
     whitelist: &AllowedAlbumIds,
-    page: Option<i32>,
+    uuids: &[String],
 ) -> Result<Vec<Album>> {
-    let entity = cache.iter().find(|e| e.name == "Album");
-    let mut select = base_select(
-        entity.expect("Couldn't find an Album entity in the entity cache"),
-        whitelist,
+    let quoted: Vec<String> = uuids.iter().filter(|u| is_uuid(u)).map(quote).collect();
+
+    let mut select = base_select(library, whitelist);
+    select.and_where_in("ZUUID", &quoted);
+
+    let records = query_as::<_, Album>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
+
+/// The albums inside the folders with the given uuids and all of their
+/// subfolders, grouped by the uuid of the folder they were found through
+pub async fn albums_in_folder_trees(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    uuids: &[String],
+) -> Result<HashMap<String, Vec<Album>>> {
+    let quoted: Vec<String> = uuids.iter().filter(|u| is_uuid(u)).map(quote).collect();
+    let tree = format!(
+        "WITH RECURSIVE tree(pk, root) AS (\
+            SELECT Z_PK, ZUUID FROM ZGENERICALBUM WHERE ZUUID IN ({}) \
+            UNION SELECT f.Z_PK, tree.root FROM ZGENERICALBUM f JOIN tree ON f.ZPARENTFOLDER = tree.pk\
+        ) SELECT pk, root FROM tree",
+        quoted.join(", ")
     );
-    if let Some(p) = page {
-        select.offset(p * 10).limit(10);
-    }
+
+    let folders = query_as::<_, (i32, String)>(tree.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    let mut select = base_select(library, whitelist);
+    select.and_where_in(
+        "ZPARENTFOLDER",
+        &folders.iter().map(|f| f.0).collect::<Vec<i32>>(),
+    );
+    select.order_asc(SORT_KEY).order_asc("Z_PK");
+
     let records = query_as::<_, Album>(select.sql()?.as_str())
-        .fetch_all(pool)
+        .fetch_all(&library.pool)
+        .await?;
+
+    let mut albums: HashMap<String, Vec<Album>> = HashMap::new();
+    for album in records {
+        for (_, root) in folders.iter().filter(|f| Some(f.0) == album.folder_id) {
+            albums.entry(root.clone()).or_default().push(album.clone());
+        }
+    }
+
+    Ok(albums)
+}
+
+/// Counts the items, photos and videos of smart albums, in a single query
+pub async fn smart_album_counts(library: &Library, ids: &[i32]) -> Result<Vec<AlbumCounts>> {
+    let mut select = SqlBuilder::select_from("ZGENERICALBUM");
+    select
+        .fields(&["Z_PK", "ZCUSTOMQUERYPARAMETERS"])
+        .and_where_in("Z_PK", ids)
+        .and_where_eq("ZKIND", SMART_ALBUM_KIND)
+        .and_where_is_not_null("ZCUSTOMQUERYPARAMETERS");
+
+    let albums = query_as::<_, (i32, Vec<u8>)>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    let mut counts = vec![];
+    for (id, blob) in albums {
+        // albums with criteria xpoz doesn't understand are left uncounted
+        let query = SmartQuery::parse(&blob);
+        let conditions = match query.and_then(|q| q.to_sql(&library.entities(), &library.model())) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+
+        let mut count = visible_assets(library);
+        count
+            .field(format!("{} as album_id", id))
+            .field("COUNT(*) as items_count")
+            .field("IFNULL(SUM(assets.ZKIND = 0), 0) as photos_count")
+            .field("IFNULL(SUM(assets.ZKIND = 1), 0) as videos_count")
+            .and_where(conditions);
+        counts.push(count.query()?);
+    }
+
+    if counts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let records = query_as::<_, AlbumCounts>(counts.join(" UNION ALL ").as_str())
+        .fetch_all(&library.pool)
         .await?;
 
     Ok(records)
 }
+
+/// Batches lookups of albums by their uuids
+pub struct AlbumLoader {
+    library: Library,
+    whitelist: AllowedAlbumIds,
+}
+
+impl AlbumLoader {
+    pub fn new(library: Library, whitelist: AllowedAlbumIds) -> Self {
+        Self { library, whitelist }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<String> for AlbumLoader {
+    type Value = Album;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, Self::Value>, Self::Error> {
+        let records = albums_by_uuid(&self.library, &self.whitelist, keys)
+            .await
+            .map_err(Arc::new)?;
+
+        Ok(records.into_iter().map(|a| (a.uuid.clone(), a)).collect())
+    }
+}
+
+/// Batches lookups of the albums in folders and their subfolders, by the
+/// uuids of the folders
+pub struct FolderAlbumsLoader {
+    library: Library,
+    whitelist: AllowedAlbumIds,
+}
+
+impl FolderAlbumsLoader {
+    pub fn new(library: Library, whitelist: AllowedAlbumIds) -> Self {
+        Self { library, whitelist }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<String> for FolderAlbumsLoader {
+    type Value = Vec<Album>;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, Self::Value>, Self::Error> {
+        albums_in_folder_trees(&self.library, &self.whitelist, keys)
+            .await
+            .map_err(Arc::new)
+    }
+}
+
+/// Batches counting the assets of smart albums, by their primary keys
+pub struct AlbumCountsLoader {
+    library: Library,
+}
+
+impl AlbumCountsLoader {
+    pub fn new(library: Library) -> Self {
+        Self { library }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i32> for AlbumCountsLoader {
+    type Value = AlbumCounts;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        let records = smart_album_counts(&self.library, keys)
+            .await
+            .map_err(Arc::new)?;
+
+        Ok(records.into_iter().map(|c| (c.album_id, c)).collect())
+    }
+}
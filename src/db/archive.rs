@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Result};
+use plist::{Dictionary, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+
+// Archives can reference themselves, so give up after a while
+const MAX_ARCHIVE_DEPTH: usize = 32;
+
+// Objects referenced from many places are copied into each of them, so a
+// small archive could otherwise unfold into a huge value
+const MAX_ARCHIVE_VALUES: usize = 100_000;
+
+/// Decodes an NSKeyedArchiver plist into plain values, resolving object
+/// references and unwrapping the Foundation collection types
+pub fn unarchive(blob: &[u8]) -> Result<Value> {
+    let archive = Value::from_reader(Cursor::new(blob))?;
+    let archive = archive
+        .as_dictionary()
+        .ok_or_else(|| anyhow!("Not a keyed archive"))?;
+    let objects = archive
+        .get("$objects")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("Keyed archive has no objects"))?;
+    let root = archive
+        .get("$top")
+        .and_then(Value::as_dictionary)
+        .and_then(|top| top.get("root"))
+        .ok_or_else(|| anyhow!("Keyed archive has no root object"))?;
+
+    Resolver::new(objects).resolve(root, 0)
+}
+
+/// Resolves the objects of an archive, remembering the ones it has resolved
+/// already, so that objects referenced many times are only resolved once
+struct Resolver<'a> {
+    objects: &'a [Value],
+    resolved: HashMap<u64, (Value, usize)>,
+    resolving: HashSet<u64>,
+    values: usize,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(objects: &'a [Value]) -> Self {
+        Self {
+            objects,
+            resolved: HashMap::new(),
+            resolving: HashSet::new(),
+            values: 0,
+        }
+    }
+
+    fn resolve(&mut self, value: &Value, depth: usize) -> Result<Value> {
+        if depth > MAX_ARCHIVE_DEPTH {
+            return Ok(Value::Boolean(false));
+        }
+
+        match value {
+            Value::Uid(uid) => self.resolve_uid(uid.get(), depth),
+            Value::Dictionary(dict) => {
+                self.count(1)?;
+
+                if let Some(Value::Array(items)) = dict.get("NS.objects") {
+                    let items = items
+                        .iter()
+                        .map(|v| self.resolve(v, depth + 1))
+                        .collect::<Result<Vec<Value>>>()?;
+
+                    if let Some(Value::Array(keys)) = dict.get("NS.keys") {
+                        let mut resolved = Dictionary::new();
+                        for (key, item) in keys.iter().zip(items) {
+                            if let Value::String(key) = self.resolve(key, depth + 1)? {
+                                resolved.insert(key, item);
+                            }
+                        }
+                        return Ok(Value::Dictionary(resolved));
+                    }
+
+                    return Ok(Value::Array(items));
+                }
+
+                if let Some(string) = dict.get("NS.string") {
+                    return self.resolve(string, depth + 1);
+                }
+
+                if let Some(time) = dict.get("NS.time") {
+                    return Ok(time.clone());
+                }
+
+                let mut resolved = Dictionary::new();
+                for (key, item) in dict {
+                    if key != "$class" {
+                        resolved.insert(key.clone(), self.resolve(item, depth + 1)?);
+                    }
+                }
+                Ok(Value::Dictionary(resolved))
+            }
+            other => {
+                self.count(1)?;
+                Ok(other.clone())
+            }
+        }
+    }
+
+    fn resolve_uid(&mut self, uid: u64, depth: usize) -> Result<Value> {
+        if let Some((value, values)) = self.resolved.get(&uid) {
+            let value = value.clone();
+            self.count(*values)?;
+            return Ok(value);
+        }
+
+        let object = match self.objects.get(uid as usize) {
+            Some(object) => object,
+            None => return Ok(Value::Boolean(false)),
+        };
+
+        // An object referencing itself, directly or not
+        if !self.resolving.insert(uid) {
+            return Ok(Value::Boolean(false));
+        }
+
+        let before = self.values;
+        let value = self.resolve(object, depth + 1)?;
+        self.resolving.remove(&uid);
+        self.resolved
+            .insert(uid, (value.clone(), self.values - before));
+
+        Ok(value)
+    }
+
+    fn count(&mut self, values: usize) -> Result<()> {
+        self.values += values;
+        if self.values > MAX_ARCHIVE_VALUES {
+            return Err(anyhow!("Keyed archive is too large"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plist::Uid;
+
+    fn dictionary(entries: Vec<(&str, Value)>) -> Value {
+        let mut dict = Dictionary::new();
+        for (key, value) in entries {
+            dict.insert(key.to_string(), value);
+        }
+        Value::Dictionary(dict)
+    }
+
+    fn uid(index: u64) -> Value {
+        Value::Uid(Uid::new(index))
+    }
+
+    fn string(value: &str) -> Value {
+        Value::String(value.to_string())
+    }
+
+    // Archives the objects with the second one as the root, the first one
+    // being the "$null" placeholder of NSKeyedArchiver
+    fn archive(objects: Vec<Value>) -> Vec<u8> {
+        let objects = std::iter::once(string("$null")).chain(objects).collect();
+        let archive = dictionary(vec![
+            ("$archiver", string("NSKeyedArchiver")),
+            ("$objects", Value::Array(objects)),
+            ("$top", dictionary(vec![("root", uid(1))])),
+        ]);
+        let mut blob = vec![];
+        archive.to_writer_binary(&mut blob).unwrap();
+        blob
+    }
+
+    #[test]
+    fn resolves_references_and_collections() {
+        let blob = archive(vec![
+            dictionary(vec![
+                ("$class", uid(5)),
+                ("name", uid(2)),
+                ("tags", uid(3)),
+                ("missing", uid(42)),
+            ]),
+            dictionary(vec![("NS.string", string("Lisbon"))]),
+            dictionary(vec![
+                ("NS.keys", Value::Array(vec![uid(4)])),
+                ("NS.objects", Value::Array(vec![uid(2)])),
+            ]),
+            string("city"),
+            dictionary(vec![("$classname", string("Place"))]),
+        ]);
+
+        assert_eq!(
+            unarchive(&blob).unwrap(),
+            dictionary(vec![
+                ("name", string("Lisbon")),
+                ("tags", dictionary(vec![("city", string("Lisbon"))])),
+                ("missing", Value::Boolean(false)),
+            ])
+        );
+    }
+
+    #[test]
+    fn stops_at_objects_referencing_themselves() {
+        let blob = archive(vec![
+            dictionary(vec![("next", uid(2))]),
+            dictionary(vec![("previous", uid(1))]),
+        ]);
+
+        assert_eq!(
+            unarchive(&blob).unwrap(),
+            dictionary(vec![(
+                "next",
+                dictionary(vec![("previous", Value::Boolean(false))])
+            )])
+        );
+    }
+
+    // Each array references the next one `copies` times, so unfolding the
+    // archive multiplies its size with every level
+    fn shared_arrays(levels: u64, copies: usize) -> Vec<u8> {
+        let mut objects: Vec<Value> = (1..levels)
+            .map(|i| dictionary(vec![("NS.objects", Value::Array(vec![uid(i + 1); copies]))]))
+            .collect();
+        objects.push(string("leaf"));
+        archive(objects)
+    }
+
+    fn leaves(value: &Value) -> usize {
+        match value {
+            Value::Array(items) => items.iter().map(leaves).sum(),
+            _ => 1,
+        }
+    }
+
+    #[test]
+    fn resolves_shared_objects() {
+        let root = unarchive(&shared_arrays(11, 2)).unwrap();
+        assert_eq!(leaves(&root), 1024);
+    }
+
+    #[test]
+    fn rejects_archives_unfolding_into_huge_values() {
+        assert!(unarchive(&shared_arrays(15, 4)).is_err());
+    }
+
+    #[test]
+    fn rejects_other_plists() {
+        let mut blob = vec![];
+        string("not an archive")
+            .to_writer_binary(&mut blob)
+            .unwrap();
+        assert!(unarchive(&blob).is_err());
+        assert!(unarchive(b"garbage").is_err());
+    }
+}
@@ -1,19 +1,96 @@
-use super::{Album, Entity};
-use crate::settings::Settings;
+use super::{
+    albums::AllowedAlbumIds,
+    dates::Timestamp,
+    entities::entity_id,
+    errors::{graphql_error, XpozError},
+    keywords::Keyword,
+    libraries::{library, Library},
+    loaders::loaders,
+    metadata::{Adjustment, AssetMetadata, Exif},
+    pagination::{Page, PagedConnection, SortCursor, Sorted},
+    people::Face,
+    places::Place,
+    resources::Resource,
+    Album, Entity,
+};
+use crate::settings::{Photos, Settings};
 use actix_files as fs;
 use anyhow::{anyhow, Result};
-use async_graphql::{Context, Object};
-use glob::{glob_with, MatchOptions};
+use async_graphql::{dataloader::Loader, Context, Enum, InputObject, Object, Result as AGResult};
+use glob::{glob, glob_with, MatchOptions};
 use sql_builder::prelude::*;
-use sqlx::{query_as, sqlite::SqlitePool};
+use sqlx::query_as;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
-#[derive(sqlx::FromRow)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum MediaType {
+    Photo,
+    Video,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum MediaSubtype {
+    Panorama,
+    LivePhoto,
+    Portrait,
+    Screenshot,
+    SloMo,
+    TimeLapse,
+}
+
+/// Narrows down the assets returned for an album
+#[derive(InputObject, Default)]
+pub struct AssetFilter {
+    /// Only return assets marked as favorite in Photos
+    #[graphql(default)]
+    favorites: bool,
+    media_type: Option<MediaType>,
+    #[graphql(default)]
+    exclude_screenshots: bool,
+    subtype: Option<MediaSubtype>,
+}
+
+impl AssetFilter {
+    fn apply(&self, builder: &mut SqlBuilder) {
+        if self.favorites {
+            builder.and_where_eq("assets.ZFAVORITE", 1);
+        }
+
+        if let Some(media_type) = self.media_type {
+            let kind = match media_type {
+                MediaType::Photo => 0,
+                MediaType::Video => 1,
+            };
+            builder.and_where_eq("assets.ZKIND", kind);
+        }
+
+        if self.exclude_screenshots {
+            builder.and_where_ne("assets.ZKINDSUBTYPE", 10);
+        }
+
+        if let Some(subtype) = self.subtype {
+            match subtype {
+                MediaSubtype::Panorama => builder.and_where_eq("assets.ZKINDSUBTYPE", 1),
+                MediaSubtype::LivePhoto => builder.and_where_eq("assets.ZKINDSUBTYPE", 2),
+                MediaSubtype::Screenshot => builder.and_where_eq("assets.ZKINDSUBTYPE", 10),
+                MediaSubtype::SloMo => builder.and_where_eq("assets.ZKINDSUBTYPE", 101),
+                MediaSubtype::TimeLapse => builder.and_where_eq("assets.ZKINDSUBTYPE", 102),
+                MediaSubtype::Portrait => builder.and_where_gt("assets.ZDEPTHTYPE", 0),
+            };
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Clone)]
 pub struct Asset {
     pub id: i32,
     uuid: String,
+    library: String,
     entity_id: i32,
-    created_at: String,
+    created_at: f64,
+    timezone_offset: Option<i32>,
     height: i32,
     width: i32,
     latitude: f32,
@@ -21,15 +98,42 @@ pub struct Asset {
     directory: String,
     filename: String,
     duration: f32,
+    kind: i32,
+    edited: bool,
+    shared: bool,
+    sort_key: i64,
+}
+
+impl Sorted for Asset {
+    fn cursor(&self) -> SortCursor {
+        SortCursor {
+            key: self.sort_key,
+            id: self.id,
+        }
+    }
 }
 
 #[Object]
 impl Asset {
-    async fn id(&self) -> &String {
-        &self.uuid
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
     }
-    async fn created_at(&self) -> &String {
-        &self.created_at
+    /// Name of the library the asset belongs to
+    async fn library(&self) -> &String {
+        &self.library
+    }
+    /// When the asset was captured, in the timezone it was captured in.
+    /// In UTC when Photos doesn't know that timezone
+    async fn created_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_time(self.created_at, self.timezone_offset)
+    }
+    async fn created_at_utc(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.created_at)
+    }
+    /// Offset of the timezone the asset was captured in, in seconds east
+    /// of UTC. Null when Photos doesn't know it
+    async fn timezone_offset(&self) -> &Option<i32> {
+        &self.timezone_offset
     }
     async fn height(&self) -> &i32 {
         &self.height
@@ -47,41 +151,201 @@ impl Asset {
         &self.duration
     }
     async fn is_video(&self) -> bool {
-        &self.duration > &0f32
+        self.duration > 0f32
+    }
+    async fn entity(&self, ctx: &Context<'_>) -> AGResult<Option<Entity>> {
+        let cache = library(ctx, &self.library)?.entities();
+        Ok(cache.iter().find(|e| e.id == self.entity_id).cloned())
+    }
+    async fn title(&self, ctx: &Context<'_>) -> AGResult<Option<String>> {
+        Ok(self.metadata(ctx).await?.and_then(|m| m.title))
+    }
+    async fn caption(&self, ctx: &Context<'_>) -> AGResult<Option<String>> {
+        Ok(self.metadata(ctx).await?.and_then(|m| m.caption))
     }
-    async fn entity<'a>(&self, ctx: &'a Context<'_>) -> Option<&'a Entity> {
-        let cache = ctx
-            .data::<Vec<Entity>>()
-            .expect("Failed getting entity cache");
-        cache.iter().find(|e| e.id == self.entity_id)
+    async fn original_filename(&self, ctx: &Context<'_>) -> AGResult<Option<String>> {
+        Ok(self.metadata(ctx).await?.and_then(|m| m.original_filename))
+    }
+    /// Size of the original file in bytes
+    async fn file_size(&self, ctx: &Context<'_>) -> AGResult<Option<i64>> {
+        Ok(self.metadata(ctx).await?.and_then(|m| m.file_size))
+    }
+    /// Whether the asset has been edited since it was imported
+    async fn is_edited(&self) -> &bool {
+        &self.edited
+    }
+    /// The latest edit made to the asset
+    async fn adjustment(&self, ctx: &Context<'_>) -> AGResult<Option<Adjustment>> {
+        if !self.edited {
+            return Ok(None);
+        }
+        Ok(self.metadata(ctx).await?.and_then(|m| m.adjustment()))
+    }
+    async fn exif(&self, ctx: &Context<'_>) -> AGResult<Option<Exif>> {
+        Ok(self.metadata(ctx).await?.map(|m| m.exif()))
+    }
+    /// Where the asset was taken, as reverse geocoded by Photos
+    async fn place(&self, ctx: &Context<'_>) -> AGResult<Option<Place>> {
+        Ok(self
+            .metadata(ctx)
+            .await?
+            .and_then(|m| m.reverse_location)
+            .and_then(|blob| Place::decode(&blob).ok()))
+    }
+    /// Keywords the asset is tagged with in Photos
+    async fn keywords(&self, ctx: &Context<'_>) -> AGResult<Vec<Keyword>> {
+        Ok(loaders(ctx, &self.library)?
+            .keywords
+            .load_one(self.id)
+            .await
+            .map_err(graphql_error)?
+            .unwrap_or_default())
+    }
+    /// Faces of the recognised people on this asset
+    async fn people(&self, ctx: &Context<'_>) -> AGResult<Vec<Face>> {
+        Ok(loaders(ctx, &self.library)?
+            .faces
+            .load_one(self.id)
+            .await
+            .map_err(graphql_error)?
+            .unwrap_or_default())
     }
 }
 
 impl Asset {
+    /// Loads the additional attributes of the asset, batched with all
+    /// other assets resolved in the same request
+    async fn metadata(&self, ctx: &Context<'_>) -> AGResult<Option<AssetMetadata>> {
+        let loader = &library(ctx, &self.library)?.metadata;
+        loader.load_one(self.id).await.map_err(graphql_error)
+    }
+
     /// Returns the original file for the asset
-    pub fn original(&self, settings: &Settings) -> Result<fs::NamedFile> {
-        let mut dir = settings.photos.originals_dir();
+    pub fn original(&self, photos: &Photos) -> Result<fs::NamedFile> {
+        if self.shared {
+            return self.shared_original(photos);
+        }
+
+        let mut dir = photos.originals_dir();
         dir.push(&self.directory);
         dir.push(&self.filename);
         Ok(fs::NamedFile::open(dir)?)
     }
 
-    /// Returns the first found resized variant of the asset with latest edits
-    pub fn resized(&self, settings: &Settings) -> Result<fs::NamedFile> {
-        let mut path = settings.photos.resized_dir();
-        self.first_in_path(&mut path)
+    /// Returns the largest resized variant of the asset with latest edits
+    pub fn resized(&self, photos: &Photos, resources: &[Resource]) -> Result<fs::NamedFile> {
+        // live photos have resized variants of both their photo and their video
+        let is_video = self.kind == 1;
+        let resized = resources
+            .iter()
+            .filter(|r| !r.is_thumb() && !r.is_render() && r.is_video() == is_video)
+            .max_by_key(|r| r.width)
+            .ok_or_else(|| XpozError::NotFound("This asset has no resized variant".to_string()))?;
+
+        self.open_resource(photos, resized)
+    }
+
+    /// Returns the original size asset with latest edits applied
+    pub fn render(&self, photos: &Photos, resources: &[Resource]) -> Result<fs::NamedFile> {
+        if !self.edited {
+            return Err(anyhow!("This asset has not been edited"));
+        }
+
+        // live photos have renders of both their photo and their video
+        let is_video = self.kind == 1;
+        let render = resources
+            .iter()
+            .find(|r| r.is_render() && r.is_video() == is_video);
+        if let Some(resource) = render {
+            return self.open_resource(photos, resource);
+        }
+
+        // Photos names full size renders after the uuid of the asset, with
+        // a suffix depending on whether it's a photo or a video
+        let filename = if is_video {
+            format!("{}_2_0_a.mov", self.uuid)
+        } else {
+            format!("{}_1_201_a.jpeg", self.uuid)
+        };
+
+        let mut path = self.derivatives_dir(photos, &photos.renders);
+        path.push(&self.directory);
+        path.push(filename);
+        Ok(fs::NamedFile::open(path)?)
+    }
+
+    /// Returns the render of edited assets and the original otherwise.
+    /// Falls back to the original when the render isn't on disk, e.g. when
+    /// Photos.app hasn't generated it yet
+    pub fn best(&self, photos: &Photos, resources: &[Resource]) -> Result<fs::NamedFile> {
+        if self.edited {
+            if let Ok(render) = self.render(photos, resources) {
+                return Ok(render);
+            }
+        }
+        self.original(photos)
+    }
+
+    /// Returns the smallest thumb of the asset with latest edits applied
+    pub fn thumb(&self, photos: &Photos, resources: &[Resource]) -> Result<fs::NamedFile> {
+        let thumb = resources
+            .iter()
+            .filter(|r| r.is_thumb())
+            .min_by_key(|r| r.width);
+
+        match thumb {
+            Some(resource) => self.open_resource(photos, resource),
+            None => {
+                let mut path = self.derivatives_dir(photos, &photos.thumbs);
+                self.first_in_path(&mut path)
+            }
+        }
+    }
+
+    fn open_resource(&self, photos: &Photos, resource: &Resource) -> Result<fs::NamedFile> {
+        let filename = resource
+            .filename(&self.uuid)
+            .ok_or_else(|| anyhow!("Requested variant for this asset is not supported"))?;
+
+        let mut path = if resource.is_thumb() {
+            self.derivatives_dir(photos, &photos.thumbs)
+        } else if resource.is_render() {
+            self.derivatives_dir(photos, &photos.renders)
+        } else {
+            self.derivatives_dir(photos, &photos.resized)
+        };
+        path.push(&self.directory);
+        path.push(filename);
+
+        Ok(fs::NamedFile::open(path)?)
     }
 
-    /// Returns the first found original size asset with latest edits applied
-    pub fn render(&self, settings: &Settings) -> Result<fs::NamedFile> {
-        let mut path = settings.photos.renders_dir();
-        self.first_in_path(&mut path)
+    /// Assets of iCloud shared albums keep the same layout of derivatives,
+    /// rooted in the cloudsharing directory instead of the library
+    fn derivatives_dir(&self, photos: &Photos, subdir: &String) -> PathBuf {
+        if !self.shared {
+            return photos.dir_to(subdir);
+        }
+
+        let mut path = photos.cloudsharing_dir();
+        path.push(subdir);
+        path
     }
 
-    /// Returns the first found thumb of the asset with latest edits applied
-    pub fn thumb(&self, settings: &Settings) -> Result<fs::NamedFile> {
-        let mut path = settings.photos.thumbs_dir();
-        self.first_in_path(&mut path)
+    /// Originals of shared assets are stored in per-album directories under
+    /// cloudsharing/data, which aren't recorded in the Photos database
+    fn shared_original(&self, photos: &Photos) -> Result<fs::NamedFile> {
+        let mut path = photos.cloudsharing_dir();
+        path.push("data");
+        path.push("**");
+        path.push(&self.directory);
+        path.push(&self.filename);
+
+        let pattern = path.to_str().expect("Failed converting a path to a string");
+        match glob(pattern)?.next() {
+            Some(entry) => Ok(fs::NamedFile::open(entry?)?),
+            None => Err(anyhow!("Original of this shared asset is not available")),
+        }
     }
 
     /// Returns the transcoded video for the asset
@@ -91,8 +355,10 @@ impl Asset {
         Ok(fs::NamedFile::open(path)?)
     }
 
+    /// Looks for a derivative by its name, for the assets Photos hasn't
+    /// recorded resources for
     fn first_in_path(&self, path: &mut PathBuf) -> Result<fs::NamedFile> {
-        let extensions = ["jpeg", "mov", "mp4", "jpg", "png", "gif"];
+        let extensions = ["jpeg", "heic", "mov", "mp4", "jpg", "png", "webp", "gif"];
 
         let options = MatchOptions {
             case_sensitive: false,
@@ -106,8 +372,9 @@ impl Asset {
 
         for ext in extensions.iter() {
             let pattern = format!("{}/{}*.{}", dir, self.uuid, ext);
-            for entry in glob_with(pattern.as_str(), options).expect("Failed to read glob pattern")
-            {
+            let mut entries =
+                glob_with(pattern.as_str(), options).expect("Failed to read glob pattern");
+            if let Some(entry) = entries.next() {
                 return Ok(fs::NamedFile::open(entry?)?);
             }
         }
@@ -116,28 +383,26 @@ impl Asset {
     }
 }
 
-fn album_join_tables(cache: &Vec<Entity>) -> (String, String, String, String) {
-    let album = cache
-        .iter()
-        .find(|e| e.name == "Album")
-        .expect("Couldn't find an 'Album' entity in the entity cache");
-    let asset = cache
-        .iter()
-        .find(|e| e.name == "Asset")
-        .expect("Couldn't find a 'Asset' entity in the entity cache");
-    let join_table = format!("Z_{}ASSETS as joins", album.id);
-    let album_fk = format!("Z_{}ALBUMS", album.id);
-    let asset_fk = format!("Z_{}ASSETS", asset.id);
-    let order_key = format!("Z_FOK_{}ASSETS", asset.id);
-    (join_table, album_fk, asset_fk, order_key)
+pub fn album_join_tables(cache: &[Entity]) -> Result<(String, String, String, String)> {
+    let album = entity_id(cache, "Album")?;
+    let asset = entity_id(cache, "Asset")?;
+    let join_table = format!("Z_{}ASSETS as joins", album);
+    let album_fk = format!("Z_{}ALBUMS", album);
+    let asset_fk = format!("Z_{}ASSETS", asset);
+    let order_key = format!("Z_FOK_{}ASSETS", asset);
+    Ok((join_table, album_fk, asset_fk, order_key))
 }
 
-fn base_select() -> SqlBuilder {
+/// `sort_key` is the sql expression exposed as the asset's position when
+/// paginating through a list of assets
+fn base_select(library: &Library, sort_key: &str) -> SqlBuilder {
     let fields = [
         "Z_PK as id",
         "ZUUID as uuid",
         "Z_ENT as entity_id",
-        "datetime(ZDATECREATED,'unixepoch','31 years','localtime') as created_at",
+        "ZDATECREATED as created_at",
+        "(SELECT ZTIMEZONEOFFSET FROM ZADDITIONALASSETATTRIBUTES \
+        WHERE ZASSET = assets.Z_PK) as timezone_offset",
         "ZHEIGHT as height",
         "ZWIDTH as width",
         "ZLATITUDE as latitude",
@@ -145,61 +410,141 @@ fn base_select() -> SqlBuilder {
         "ZDIRECTORY as directory",
         "ZFILENAME as filename",
         "ZDURATION as duration",
+        "ZKIND as kind",
+        "IFNULL(ZHASADJUSTMENTS, 0) as edited",
+        "ZCLOUDBATCHPUBLISHDATE IS NOT NULL as shared",
     ];
 
-    let mut builder = SqlBuilder::select_from("ZASSET as assets");
+    let mut builder = visible_assets(library);
 
     builder
         .fields(&fields)
-        .and_where_lt("assets.ZTRASHEDSTATE", 1);
+        .field(library.name_field())
+        .field(format!("{} as sort_key", sort_key));
+
+    builder
+}
+
+/// Sql conditions on the `assets` table matching everything a whitelist
+/// gives access to
+pub fn asset_scope(library: &Library, whitelist: &AllowedAlbumIds) -> Result<String> {
+    match whitelist {
+        None => Ok("1".to_string()),
+        Some(wl) => wl.asset_conditions(library),
+    }
+}
+
+/// Selects from the assets which aren't trashed or hidden in Photos
+pub fn visible_assets(library: &Library) -> SqlBuilder {
+    let mut builder = SqlBuilder::select_from(format!("{} as assets", library.model().assets));
+
+    builder
+        .and_where_lt("assets.ZTRASHEDSTATE", 1)
+        .and_where_lt("assets.ZHIDDEN", 1);
 
     builder
 }
 
-pub async fn asset(pool: &SqlitePool, uuid: &String) -> Result<Option<Asset>> {
-    let mut select = base_select();
-    select.and_where("ZUUID = ?".bind(uuid));
+pub async fn asset(library: &Library, uuid: &str) -> Result<Option<Asset>> {
+    let mut select = base_select(library, "assets.Z_PK");
+    select.and_where("ZUUID = ?".bind(&uuid));
 
     let record = query_as::<_, Asset>(select.sql()?.as_str())
-        .fetch_optional(pool)
+        .fetch_optional(&library.pool)
         .await?;
 
     Ok(record)
 }
 
+async fn paginate(
+    library: &Library,
+    mut select: SqlBuilder,
+    order_key: &str,
+    page: &Page,
+) -> Result<PagedConnection<Asset>> {
+    let mut count = select.clone();
+    count.set_field("COUNT(*)");
+    let (total_count,) = query_as::<_, (i64,)>(count.sql()?.as_str())
+        .fetch_one(&library.pool)
+        .await?;
+
+    page.apply(&mut select, order_key, "assets.Z_PK")?;
+    let records = query_as::<_, Asset>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    page.connection(records, total_count)
+}
+
 pub async fn assets(
-    pool: &SqlitePool,
-    cache: &Vec<Entity>,
+    library: &Library,
     album: &Album,
-    offset: i32,
-    limit: i32,
-) -> Result<Vec<Asset>> {
-    let joins = album_join_tables(cache);
+    filter: &AssetFilter,
+    page: &Page,
+) -> Result<PagedConnection<Asset>> {
+    let joins = album_join_tables(&library.entities())?;
+    let order_key = format!("joins.{}", joins.3);
 
-    let mut select = base_select();
+    let mut select = base_select(library, &order_key);
 
     select
         .join(joins.0)
         .on(format!("joins.{} = assets.Z_PK", joins.2))
-        .and_where_eq(format!("joins.{}", joins.1), album.id)
-        .offset(offset)
-        .limit(limit)
-        .order_asc(joins.3);
+        .and_where_eq(format!("joins.{}", joins.1), album.id);
 
-    let records = query_as::<_, Asset>(select.sql()?.as_str())
-        .fetch_all(pool)
-        .await?;
+    filter.apply(&mut select);
 
-    Ok(records)
+    paginate(library, select, &order_key, page).await
+}
+
+/// Assets matching arbitrary sql conditions, sorted by date
+pub async fn assets_matching(
+    library: &Library,
+    conditions: &str,
+    filter: &AssetFilter,
+    page: &Page,
+) -> Result<PagedConnection<Asset>> {
+    let order_key = "CAST(assets.ZDATECREATED AS INTEGER)";
+
+    let mut select = base_select(library, order_key);
+    select.and_where(format!("({})", conditions));
+
+    filter.apply(&mut select);
+
+    paginate(library, select, order_key, page).await
 }
 
-pub async fn assets_by_id(pool: &SqlitePool, ids: &Vec<i32>) -> Result<Vec<Asset>> {
-    let mut select = base_select();
+pub async fn assets_by_id(library: &Library, ids: &[i32]) -> Result<Vec<Asset>> {
+    let mut select = base_select(library, "assets.Z_PK");
     select.and_where_in("Z_PK", ids);
 
     let records = query_as::<_, Asset>(select.sql()?.as_str())
-        .fetch_all(pool)
+        .fetch_all(&library.pool)
         .await?;
 
     Ok(records)
 }
+
+/// Batches lookups of assets by their primary key, e.g. the key assets of
+/// all albums resolved in a single request
+pub struct AssetLoader {
+    library: Library,
+}
+
+impl AssetLoader {
+    pub fn new(library: Library) -> Self {
+        Self { library }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i32> for AssetLoader {
+    type Value = Asset;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        let records = assets_by_id(&self.library, keys).await.map_err(Arc::new)?;
+
+        Ok(records.into_iter().map(|a| (a.id, a)).collect())
+    }
+}
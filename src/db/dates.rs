@@ -0,0 +1,99 @@
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+
+/// Seconds between the unix epoch and the Apple (Core Data) epoch of
+/// 2001-01-01 00:00:00 UTC which Photos stores its dates relative to
+pub const APPLE_EPOCH: i64 = 978_307_200;
+
+/// A point in time along with the timezone offset it was recorded in,
+/// exposed as an RFC 3339 string
+#[derive(Clone, Copy)]
+pub struct Timestamp(DateTime<FixedOffset>);
+
+impl Timestamp {
+    /// Converts seconds since the Apple epoch, shifting them to a timezone
+    /// `offset` seconds east of UTC. Stays in UTC when the offset isn't
+    /// known, and gives up on times chrono can't represent
+    pub fn from_apple_time(seconds: f64, offset: Option<i32>) -> Option<Self> {
+        let whole = seconds.floor();
+        let nanos = ((seconds - whole) * 1e9) as u32;
+        let utc = Utc
+            .timestamp_opt((whole as i64).checked_add(APPLE_EPOCH)?, nanos)
+            .single()?;
+        let offset = offset
+            .and_then(FixedOffset::east_opt)
+            .unwrap_or_else(|| FixedOffset::east(0));
+        Some(Self(utc.with_timezone(&offset)))
+    }
+
+    /// Converts seconds since the Apple epoch to a UTC timestamp
+    pub fn from_apple_utc(seconds: f64) -> Option<Self> {
+        Self::from_apple_time(seconds, None)
+    }
+}
+
+#[Scalar(name = "DateTime")]
+impl ScalarType for Timestamp {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(s) => Ok(Self(DateTime::parse_from_rfc3339(s)?)),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.to_rfc3339())
+    }
+}
+
+/// Sql expression of the capture time of an asset in the `assets` table as
+/// unix time, shifted to the timezone it was taken in. Pass it to sqlite's
+/// date functions with the 'unixepoch' modifier
+pub fn local_capture_time() -> String {
+    format!(
+        "(assets.ZDATECREATED + {} + IFNULL((SELECT ZTIMEZONEOFFSET \
+        FROM ZADDITIONALASSETATTRIBUTES WHERE ZASSET = assets.Z_PK), 0))",
+        APPLE_EPOCH
+    )
+}
+
+/// Sql expression of the local date an asset was captured on, formatted
+/// as YYYY-MM-DD
+pub fn local_capture_date() -> String {
+    format!("date({},'unixepoch')", local_capture_time())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc3339(timestamp: Option<Timestamp>) -> Option<String> {
+        timestamp.map(|t| t.0.to_rfc3339())
+    }
+
+    #[test]
+    fn shifts_to_the_recorded_offset() {
+        assert_eq!(
+            rfc3339(Timestamp::from_apple_time(599529600.5, Some(3600))),
+            Some("2020-01-01T01:00:00.500+01:00".to_string())
+        );
+    }
+
+    #[test]
+    fn stays_in_utc_without_an_offset() {
+        assert_eq!(
+            rfc3339(Timestamp::from_apple_time(599529600.0, None)),
+            Some("2020-01-01T00:00:00+00:00".to_string())
+        );
+        assert_eq!(
+            rfc3339(Timestamp::from_apple_time(599529600.0, Some(i32::MAX))),
+            rfc3339(Timestamp::from_apple_utc(599529600.0))
+        );
+    }
+
+    #[test]
+    fn gives_up_on_times_out_of_range() {
+        assert!(Timestamp::from_apple_utc(1e300).is_none());
+        assert!(Timestamp::from_apple_utc(-1e300).is_none());
+    }
+}
@@ -1,13 +1,16 @@
-use anyhow::Result;
-use async_graphql::{Context, Object};
+use super::libraries::library;
+use anyhow::{anyhow, Result};
+use async_graphql::{Context, Object, Result as AGResult};
 use sql_builder::prelude::*;
 use sqlx::{query_as, sqlite::SqlitePool};
+use std::sync::{Arc, RwLock};
 
-#[derive(sqlx::FromRow)]
+#[derive(sqlx::FromRow, Clone, PartialEq)]
 pub struct Entity {
     pub id: i32,
     pub name: String,
     parent_id: Option<i32>,
+    library: String,
 }
 
 #[Object]
@@ -21,25 +24,38 @@ impl Entity {
     async fn parent_id(&self) -> &Option<i32> {
         &self.parent_id
     }
-    async fn parent<'a>(&self, ctx: &'a Context<'_>) -> Option<&'a Self> {
-        let cache = ctx
-            .data::<Vec<Entity>>()
-            .expect("Couldn't get entity cache");
-        cache.iter().find(|e| Some(e.id) == self.parent_id)
+    async fn parent(&self, ctx: &Context<'_>) -> AGResult<Option<Self>> {
+        let cache = library(ctx, &self.library)?.entities();
+        Ok(cache.iter().find(|e| Some(e.id) == self.parent_id).cloned())
     }
 }
 
-fn base_select() -> SqlBuilder {
+#[cfg(test)]
+impl Entity {
+    pub fn new(id: i32, name: &str) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            parent_id: None,
+            library: String::new(),
+        }
+    }
+}
+
+fn base_select(library: &str) -> SqlBuilder {
     let fields = ["Z_ENT as id", "Z_NAME as name", "Z_SUPER as parent_id"];
 
     let mut builder = SqlBuilder::select_from("Z_PRIMARYKEY");
-    builder.fields(&fields);
+    builder
+        .fields(&fields)
+        .field(format!("{} as library", quote(library)));
 
     builder
 }
 
-pub async fn entities(pool: &SqlitePool) -> Result<Vec<Entity>> {
-    let select = base_select();
+/// Loads the entities of the library with the given name
+pub async fn entities(pool: &SqlitePool, library: &str) -> Result<Vec<Entity>> {
+    let select = base_select(library);
     let records = query_as::<_, Entity>(select.sql()?.as_str())
         .fetch_all(pool)
         .await?;
@@ -47,3 +63,40 @@ pub async fn entities(pool: &SqlitePool) -> Result<Vec<Entity>> {
     Ok(records)
 }
 
+/// Looks up the id of an entity in the entity cache by its name
+pub fn entity_id(cache: &[Entity], name: &str) -> Result<i32> {
+    cache
+        .iter()
+        .find(|e| e.name == name)
+        .map(|e| e.id)
+        .ok_or_else(|| anyhow!("Couldn't find a '{}' entity in the entity cache", name))
+}
+
+/// The entities of a library. Photos.app can add entities or renumber
+/// them when it migrates its database, so the cache can be swapped for
+/// freshly loaded entities while queries keep using the ones they started with
+#[derive(Clone)]
+pub struct EntityCache(Arc<RwLock<Arc<Vec<Entity>>>>);
+
+impl EntityCache {
+    pub fn new(entities: Vec<Entity>) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(entities))))
+    }
+
+    pub fn get(&self) -> Arc<Vec<Entity>> {
+        self.0
+            .read()
+            .expect("Entity cache lock is poisoned")
+            .clone()
+    }
+
+    /// Replaces the cached entities, returning whether they have changed
+    pub fn replace(&self, entities: Vec<Entity>) -> bool {
+        let mut cache = self.0.write().expect("Entity cache lock is poisoned");
+        if **cache == entities {
+            return false;
+        }
+        *cache = Arc::new(entities);
+        true
+    }
+}
@@ -0,0 +1,60 @@
+use async_graphql::{Error, ErrorExtensions};
+use std::borrow::Borrow;
+use std::fmt;
+
+/// Errors reported to GraphQL clients. Each kind is exposed with a stable
+/// `code` extension, modelled after the matching HTTP status
+#[derive(Debug)]
+pub enum XpozError {
+    Unauthorized,
+    Forbidden,
+    NotFound(String),
+    Validation(String),
+    Internal,
+}
+
+impl XpozError {
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Unauthorized => 401,
+            Self::Forbidden => 403,
+            Self::NotFound(_) => 404,
+            Self::Validation(_) => 400,
+            Self::Internal => 500,
+        }
+    }
+}
+
+impl fmt::Display for XpozError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthorized => write!(f, "Unauthorised"),
+            Self::Forbidden => write!(f, "Forbidden"),
+            Self::NotFound(message) | Self::Validation(message) => write!(f, "{}", message),
+            Self::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl std::error::Error for XpozError {}
+
+impl ErrorExtensions for XpozError {
+    fn extend(&self) -> Error {
+        let code = self.code();
+        Error::new(self.to_string()).extend_with(|_, e| e.set("code", code))
+    }
+}
+
+/// Turns an error raised while resolving a query into one fit for clients.
+/// `XpozError`s keep their message, while the details of anything else,
+/// such as failed sql queries, are only logged
+pub fn graphql_error(error: impl Borrow<anyhow::Error>) -> Error {
+    let error = error.borrow();
+    match error.downcast_ref::<XpozError>() {
+        Some(e) => e.extend(),
+        None => {
+            log::error!("{:?}", error);
+            XpozError::Internal.extend()
+        }
+    }
+}
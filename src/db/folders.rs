@@ -0,0 +1,174 @@
+use super::{
+    albums::{folder_albums, Album, AllowedAlbumIds},
+    entities::entity_id,
+    errors::graphql_error,
+    libraries::{library, Library},
+    pagination::{Page, PagedConnection},
+    tokens::Token,
+};
+use anyhow::Result;
+use async_graphql::{connection::query, Context, Object, Result as AGResult};
+use sql_builder::prelude::*;
+use sqlx::query_as;
+
+// ZKIND of the top level folder holding all user albums and folders
+const ROOT_FOLDER_KIND: i32 = 3999;
+
+#[derive(sqlx::FromRow)]
+pub struct Folder {
+    pub id: i32,
+    uuid: String,
+    library: String,
+    title: Option<String>,
+    parent_id: Option<i32>,
+}
+
+#[Object]
+impl Folder {
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
+    }
+    /// Name of the library the folder belongs to
+    async fn library(&self) -> &String {
+        &self.library
+    }
+    async fn title(&self) -> &Option<String> {
+        &self.title
+    }
+
+    /// The parent folder, unless it's not accessible
+    async fn parent(&self, ctx: &Context<'_>) -> AGResult<Option<Folder>> {
+        let library = library(ctx, &self.library)?;
+        match self.parent_id {
+            None => Ok(None),
+            Some(id) => Ok(
+                folder_by_id(library, &ctx.data::<Token>()?.whitelist_for(library), id)
+                    .await
+                    .map_err(graphql_error)?,
+            ),
+        }
+    }
+
+    async fn children(&self, ctx: &Context<'_>) -> AGResult<Vec<Folder>> {
+        let library = library(ctx, &self.library)?;
+        Ok(subfolders(
+            library,
+            &ctx.data::<Token>()?.whitelist_for(library),
+            self.id,
+        )
+        .await
+        .map_err(graphql_error)?)
+    }
+
+    async fn albums(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> AGResult<PagedConnection<Album>> {
+        let library = library(ctx, &self.library)?;
+        let whitelist = ctx.data::<Token>()?.whitelist_for(library);
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                folder_albums(
+                    library,
+                    &whitelist,
+                    self.id,
+                    &Page::new(after, before, first, last),
+                )
+                .await
+                .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+}
+
+fn base_select(library: &Library, whitelist: &AllowedAlbumIds) -> Result<SqlBuilder> {
+    let fields = [
+        "Z_PK as id",
+        "ZUUID as uuid",
+        "ZTITLE as title",
+        "ZPARENTFOLDER as parent_id",
+    ];
+
+    let mut builder = SqlBuilder::select_from("ZGENERICALBUM");
+    builder
+        .fields(&fields)
+        .field(library.name_field())
+        .and_where_eq("Z_ENT", entity_id(&library.entities(), "Folder")?)
+        .and_where_lt("ZTRASHEDSTATE", 1);
+
+    if let Some(wl) = whitelist {
+        builder.and_where_in_query("Z_PK", wl.folder_tree());
+    }
+
+    Ok(builder)
+}
+
+pub async fn folder(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    uuid: &str,
+) -> Result<Option<Folder>> {
+    let mut select = base_select(library, whitelist)?;
+    select.and_where("ZUUID = ?".bind(&uuid));
+
+    let result = query_as::<_, Folder>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+pub async fn folder_by_id(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    id: i32,
+) -> Result<Option<Folder>> {
+    let mut select = base_select(library, whitelist)?;
+    select.and_where_eq("Z_PK", id);
+
+    let result = query_as::<_, Folder>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+/// The top level folder of the library. It's only accessible
+/// when there are no whitelisting restrictions
+pub async fn root_folder(library: &Library, whitelist: &AllowedAlbumIds) -> Result<Option<Folder>> {
+    let mut select = base_select(library, whitelist)?;
+    select.and_where_eq("ZKIND", ROOT_FOLDER_KIND);
+
+    let result = query_as::<_, Folder>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+/// Folders directly inside a folder, keeping the original Photos sorting
+pub async fn subfolders(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    parent_id: i32,
+) -> Result<Vec<Folder>> {
+    let mut select = base_select(library, whitelist)?;
+    select
+        .and_where_eq("ZPARENTFOLDER", parent_id)
+        .order_asc("Z_FOK_PARENTFOLDER");
+
+    let records = query_as::<_, Folder>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
@@ -0,0 +1,205 @@
+use super::{
+    albums::AllowedAlbumIds,
+    assets::{assets_matching, Asset, AssetFilter},
+    entities::{entity_id, Entity},
+    errors::graphql_error,
+    libraries::{library, Library},
+    pagination::{Page, PagedConnection},
+};
+use anyhow::Result;
+use async_graphql::{connection::query, dataloader::Loader, Context, Object, Result as AGResult};
+use sql_builder::prelude::*;
+use sqlx::query_as;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(sqlx::FromRow, Clone)]
+pub struct Keyword {
+    pub id: i32,
+    uuid: String,
+    library: String,
+    title: String,
+    asset_count: i32,
+    /// The asset the keyword was loaded for, when loading the keywords of
+    /// assets
+    #[sqlx(default)]
+    asset_id: Option<i32>,
+}
+
+#[Object]
+impl Keyword {
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
+    }
+    /// Name of the library the keyword belongs to
+    async fn library(&self) -> &String {
+        &self.library
+    }
+    async fn title(&self) -> &String {
+        &self.title
+    }
+    async fn asset_count(&self) -> &i32 {
+        &self.asset_count
+    }
+
+    async fn assets(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        filter: Option<AssetFilter>,
+    ) -> AGResult<PagedConnection<Asset>> {
+        let library = library(ctx, &self.library)?;
+        let filter = filter.unwrap_or_default();
+        let conditions = format!(
+            "assets.Z_PK IN ({})",
+            keyword_assets(&library.entities(), &format!("kw.Z_PK = {}", self.id))?
+        );
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                assets_matching(
+                    library,
+                    &conditions,
+                    &filter,
+                    &Page::new(after, before, first, last),
+                )
+                .await
+                .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+}
+
+/// A subquery selecting the primary keys of the assets tagged with the
+/// keywords matching the sql conditions on the `kw` table
+pub fn keyword_assets(cache: &[Entity], conditions: &str) -> Result<String> {
+    let attrs = entity_id(cache, "AdditionalAssetAttributes")?;
+    let keyword = entity_id(cache, "Keyword")?;
+    Ok(format!(
+        "SELECT attrs.ZASSET FROM ZADDITIONALASSETATTRIBUTES attrs \
+        JOIN Z_{a}KEYWORDS kw_joins ON kw_joins.Z_{a}ASSETATTRIBUTES = attrs.Z_PK \
+        JOIN ZKEYWORD kw ON kw.Z_PK = kw_joins.Z_{k}KEYWORDS \
+        WHERE {conditions}",
+        a = attrs,
+        k = keyword,
+        conditions = conditions
+    ))
+}
+
+fn base_select(library: &Library, whitelist: &AllowedAlbumIds) -> Result<SqlBuilder> {
+    let attrs = entity_id(&library.entities(), "AdditionalAssetAttributes")?;
+    let keyword = entity_id(&library.entities(), "Keyword")?;
+
+    let asset_count = format!(
+        "(SELECT COUNT(*) FROM Z_{a}KEYWORDS kw_joins \
+        WHERE kw_joins.Z_{k}KEYWORDS = kw.Z_PK) as asset_count",
+        a = attrs,
+        k = keyword
+    );
+
+    let mut builder = SqlBuilder::select_from("ZKEYWORD as kw");
+    builder
+        .fields(&["kw.Z_PK as id", "kw.ZUUID as uuid", "kw.ZTITLE as title"])
+        .field(asset_count)
+        .field(library.name_field())
+        .and_where_is_not_null("kw.ZTITLE")
+        .and_where_ne("kw.ZTITLE", quote(""));
+
+    if let Some(wl) = whitelist {
+        builder.and_where_in_quoted("kw.ZUUID", &wl.keywords);
+    }
+
+    Ok(builder)
+}
+
+pub async fn keywords(library: &Library, whitelist: &AllowedAlbumIds) -> Result<Vec<Keyword>> {
+    let mut select = base_select(library, whitelist)?;
+    select.order_asc("kw.ZTITLE");
+
+    let records = query_as::<_, Keyword>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
+
+pub async fn keyword(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    uuid: &str,
+) -> Result<Option<Keyword>> {
+    let mut select = base_select(library, whitelist)?;
+    select.and_where("kw.ZUUID = ?".bind(&uuid));
+
+    let result = query_as::<_, Keyword>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+/// The keywords the assets with the given primary keys are tagged with,
+/// grouped by asset
+pub async fn keywords_of_assets(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    asset_ids: &[i32],
+) -> Result<HashMap<i32, Vec<Keyword>>> {
+    let attrs = entity_id(&library.entities(), "AdditionalAssetAttributes")?;
+    let keyword = entity_id(&library.entities(), "Keyword")?;
+
+    let mut select = base_select(library, whitelist)?;
+    select
+        .field("attrs.ZASSET as asset_id")
+        .join(format!("Z_{}KEYWORDS as kw_joins", attrs))
+        .on(format!("kw_joins.Z_{}KEYWORDS = kw.Z_PK", keyword))
+        .join("ZADDITIONALASSETATTRIBUTES as attrs")
+        .on(format!("attrs.Z_PK = kw_joins.Z_{}ASSETATTRIBUTES", attrs))
+        .and_where_in("attrs.ZASSET", asset_ids)
+        .order_asc("kw.ZTITLE");
+
+    let records = query_as::<_, Keyword>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    let mut keywords: HashMap<i32, Vec<Keyword>> = HashMap::new();
+    for keyword in records {
+        if let Some(asset_id) = keyword.asset_id {
+            keywords.entry(asset_id).or_default().push(keyword);
+        }
+    }
+
+    Ok(keywords)
+}
+
+/// Batches lookups of the keywords of assets, by the primary keys of the
+/// assets
+pub struct KeywordsLoader {
+    library: Library,
+    whitelist: AllowedAlbumIds,
+}
+
+impl KeywordsLoader {
+    pub fn new(library: Library, whitelist: AllowedAlbumIds) -> Self {
+        Self { library, whitelist }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i32> for KeywordsLoader {
+    type Value = Vec<Keyword>;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        keywords_of_assets(&self.library, &self.whitelist, keys)
+            .await
+            .map_err(Arc::new)
+    }
+}
@@ -0,0 +1,135 @@
+use super::{
+    entities::{entities, Entity, EntityCache},
+    errors::{graphql_error, XpozError},
+    metadata::MetadataLoader,
+    model::{detect_model, Model, ModelCache},
+    places::PlaceCache,
+};
+use crate::settings::Photos;
+use anyhow::Result;
+use async_graphql::{dataloader::DataLoader, Context, Result as AGResult};
+use sql_builder::quote;
+use sqlx::sqlite::SqlitePool;
+use std::sync::Arc;
+
+/// A Photos library along with its database and entity cache
+#[derive(Clone)]
+pub struct Library {
+    pub name: String,
+    /// Records of the default library keep their plain Photos uuids as ids
+    pub default: bool,
+    pub pool: SqlitePool,
+    entities: EntityCache,
+    pub settings: Photos,
+    model: ModelCache,
+    pub metadata: Arc<DataLoader<MetadataLoader>>,
+    pub places: PlaceCache,
+}
+
+impl Library {
+    pub fn new(
+        settings: Photos,
+        default: bool,
+        pool: SqlitePool,
+        model: Model,
+        entities: Vec<Entity>,
+    ) -> Self {
+        Self {
+            name: settings.name.clone(),
+            default,
+            metadata: Arc::new(DataLoader::new(MetadataLoader::new(pool.clone()))),
+            pool,
+            entities: EntityCache::new(entities),
+            settings,
+            model: ModelCache::new(model),
+            places: PlaceCache::default(),
+        }
+    }
+
+    /// The entities of the library as they were last loaded
+    pub fn entities(&self) -> Arc<Vec<Entity>> {
+        self.entities.get()
+    }
+
+    /// The model of the library as it was last detected
+    pub fn model(&self) -> Model {
+        self.model.get()
+    }
+
+    /// Detects the model and loads the entities of the library again,
+    /// returning whether either has changed since they were last loaded
+    pub async fn reload(&self) -> Result<bool> {
+        let model = detect_model(&self.pool).await?;
+        let entities = entities(&self.pool, &self.name).await?;
+        let model_changed = self.model.replace(model);
+        let entities_changed = self.entities.replace(entities);
+        Ok(model_changed || entities_changed)
+    }
+
+    /// Sql expression selecting the name of the library as `library`, so
+    /// records know where they were loaded from
+    pub fn name_field(&self) -> String {
+        format!("{} as library", quote(&self.name))
+    }
+
+    /// The id a record of this library is exposed with
+    pub fn qualify(&self, uuid: &str) -> String {
+        if self.default {
+            uuid.to_string()
+        } else {
+            format!("{}:{}", self.name, uuid)
+        }
+    }
+
+    /// The Photos uuid of a record if the id belongs to this library
+    pub fn uuid<'a>(&self, id: &'a str) -> Option<&'a str> {
+        let mut parts = id.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(uuid)) if name == self.name => Some(uuid),
+            (Some(uuid), None) if self.default => Some(uuid),
+            _ => None,
+        }
+    }
+}
+
+/// All libraries being served, the first one being the default
+#[derive(Clone)]
+pub struct Libraries(Vec<Library>);
+
+impl Libraries {
+    pub fn new(libraries: Vec<Library>) -> Self {
+        Self(libraries)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Library> {
+        self.0.iter()
+    }
+
+    /// Looks up a library by its name, falling back to the default one
+    pub fn get(&self, name: Option<&str>) -> Result<&Library> {
+        match name {
+            None => self.0.first().ok_or_else(|| {
+                XpozError::NotFound("No Photos library is configured".to_string()).into()
+            }),
+            Some(name) => self.0.iter().find(|l| l.name == name).ok_or_else(|| {
+                XpozError::NotFound(format!("There's no library called '{}'", name)).into()
+            }),
+        }
+    }
+
+    /// Finds the library a (possibly library qualified) id belongs to,
+    /// along with the Photos uuid of the record
+    pub fn resolve<'a>(&self, id: &'a str) -> Result<(&Library, &'a str)> {
+        self.0
+            .iter()
+            .find_map(|l| l.uuid(id).map(|uuid| (l, uuid)))
+            .ok_or_else(|| XpozError::Validation(format!("Invalid id {}", id)).into())
+    }
+}
+
+/// The library a record resolved in a GraphQL query was loaded from
+pub fn library<'a>(ctx: &'a Context<'_>, name: &str) -> AGResult<&'a Library> {
+    ctx.data::<Libraries>()?
+        .get(Some(name))
+        .map_err(graphql_error)
+}
@@ -0,0 +1,71 @@
+use super::{
+    albums::{AlbumCountsLoader, AlbumLoader, FolderAlbumsLoader},
+    assets::AssetLoader,
+    errors::XpozError,
+    keywords::KeywordsLoader,
+    libraries::Libraries,
+    people::FacesLoader,
+    tokens::Token,
+};
+use async_graphql::{dataloader::DataLoader, Context, ErrorExtensions, Result as AGResult};
+
+/// The loaders of a single library. Album, keyword and face lookups are
+/// limited to what the token of the request has access to
+pub struct LibraryLoaders {
+    library: String,
+    pub assets: DataLoader<AssetLoader>,
+    pub albums: DataLoader<AlbumLoader>,
+    pub folder_albums: DataLoader<FolderAlbumsLoader>,
+    pub album_counts: DataLoader<AlbumCountsLoader>,
+    pub keywords: DataLoader<KeywordsLoader>,
+    pub faces: DataLoader<FacesLoader>,
+}
+
+/// Batches the lookups of records made while resolving a single request,
+/// so that lists resolve in a constant number of queries instead of one
+/// query per item. They are created per request, as they depend on the
+/// token making it
+pub struct Loaders(Vec<LibraryLoaders>);
+
+impl Loaders {
+    pub fn new(libraries: &Libraries, token: &Token) -> Self {
+        Self(
+            libraries
+                .iter()
+                .map(|library| LibraryLoaders {
+                    library: library.name.clone(),
+                    assets: DataLoader::new(AssetLoader::new(library.clone())),
+                    albums: DataLoader::new(AlbumLoader::new(
+                        library.clone(),
+                        token.whitelist_for(library),
+                    )),
+                    folder_albums: DataLoader::new(FolderAlbumsLoader::new(
+                        library.clone(),
+                        token.whitelist_for(library),
+                    )),
+                    album_counts: DataLoader::new(AlbumCountsLoader::new(library.clone())),
+                    keywords: DataLoader::new(KeywordsLoader::new(
+                        library.clone(),
+                        token.whitelist_for(library),
+                    )),
+                    faces: DataLoader::new(FacesLoader::new(
+                        library.clone(),
+                        token.whitelist_for(library),
+                    )),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The loaders for the library a record resolved in a GraphQL query was
+/// loaded from
+pub fn loaders<'a>(ctx: &'a Context<'_>, name: &str) -> AGResult<&'a LibraryLoaders> {
+    ctx.data::<Loaders>()?
+        .0
+        .iter()
+        .find(|l| l.library == name)
+        .ok_or_else(|| {
+            XpozError::NotFound(format!("There's no library called '{}'", name)).extend()
+        })
+}
@@ -0,0 +1,237 @@
+use super::{
+    assets::{visible_assets, Asset},
+    errors::{graphql_error, XpozError},
+    libraries::Library,
+    loaders::loaders,
+};
+use anyhow::Result;
+use async_graphql::{Context, InputObject, Object, Result as AGResult};
+use sql_builder::prelude::*;
+use sqlx::query_as;
+
+// Photos uses this for both coordinates of assets without a location
+const NO_LOCATION: f64 = -180.0;
+
+// Size of a cluster in degrees at zoom level 0. Each zoom level halves it,
+// which keeps clusters roughly the same size on screen
+const CLUSTER_SIZE: f64 = 90.0;
+
+const MAX_ZOOM: i32 = 22;
+
+// Clusters beyond the largest ones are left out, so that zooming in on a
+// large area can't list every single asset
+const MAX_CLUSTERS: usize = 500;
+
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// The visible area of a map. `west` is greater than `east` when the area
+/// crosses the antimeridian
+#[derive(InputObject)]
+pub struct BoundingBox {
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+}
+
+impl BoundingBox {
+    fn conditions(&self) -> String {
+        let longitude = if self.west <= self.east {
+            "assets.ZLONGITUDE BETWEEN ? AND ?"
+        } else {
+            "assets.ZLONGITUDE >= ? OR assets.ZLONGITUDE <= ?"
+        };
+        format!(
+            "{} AND ({})",
+            "assets.ZLATITUDE BETWEEN ? AND ?".binds(&[&self.south, &self.north]),
+            longitude.binds(&[&self.west, &self.east])
+        )
+    }
+}
+
+/// A group of assets taken close to each other
+#[derive(sqlx::FromRow)]
+pub struct Cluster {
+    library: String,
+    count: i32,
+    latitude: f64,
+    longitude: f64,
+    asset_id: i32,
+}
+
+#[Object]
+impl Cluster {
+    async fn count(&self) -> &i32 {
+        &self.count
+    }
+    /// Latitude of the centroid of the clustered assets
+    async fn latitude(&self) -> &f64 {
+        &self.latitude
+    }
+    /// Longitude of the centroid of the clustered assets
+    async fn longitude(&self) -> &f64 {
+        &self.longitude
+    }
+    /// An asset representing the cluster
+    async fn asset(&self, ctx: &Context<'_>) -> AGResult<Option<Asset>> {
+        Ok(loaders(ctx, &self.library)?
+            .assets
+            .load_one(self.asset_id)
+            .await
+            .map_err(graphql_error)?)
+    }
+}
+
+/// Sql conditions on the `assets` table leaving out assets without a location
+pub fn with_location() -> String {
+    format!(
+        "assets.ZLATITUDE IS NOT NULL AND assets.ZLONGITUDE IS NOT NULL \
+        AND assets.ZLATITUDE <> {no_location} AND assets.ZLONGITUDE <> {no_location}",
+        no_location = NO_LOCATION
+    )
+}
+
+/// Sql conditions on the `assets` table matching the assets within `radius`
+/// meters of a location. Uses an equirectangular approximation, which is
+/// accurate enough for the distances you'd browse photos by
+pub fn near(latitude: f64, longitude: f64, radius: f64) -> Result<String> {
+    if radius <= 0.0 {
+        return Err(XpozError::Validation(
+            "Radius must be a positive number of meters".to_string(),
+        )
+        .into());
+    }
+
+    let degrees = radius / METERS_PER_DEGREE;
+    let scale = latitude.to_radians().cos();
+
+    let distance = "assets.ZLATITUDE BETWEEN ? AND ? \
+        AND ((assets.ZLONGITUDE - ?) * ?) * ((assets.ZLONGITUDE - ?) * ?) \
+        + (assets.ZLATITUDE - ?) * (assets.ZLATITUDE - ?) <= ? * ?"
+        .binds(&[
+            &(latitude - degrees),
+            &(latitude + degrees),
+            &longitude,
+            &scale,
+            &longitude,
+            &scale,
+            &latitude,
+            &latitude,
+            &degrees,
+            &degrees,
+        ]);
+
+    Ok(format!("{} AND {}", with_location(), distance))
+}
+
+/// Clusters the assets within a bounding box on a grid matching the zoom level
+pub async fn clusters(
+    library: &Library,
+    scope: &str,
+    bbox: &BoundingBox,
+    zoom: i32,
+) -> Result<Vec<Cluster>> {
+    if !(0..=MAX_ZOOM).contains(&zoom) {
+        return Err(
+            XpozError::Validation(format!("Zoom must be between 0 and {}", MAX_ZOOM)).into(),
+        );
+    }
+
+    let cell = CLUSTER_SIZE / 2f64.powi(zoom);
+    let fields = [
+        "COUNT(*) as count",
+        "AVG(assets.ZLATITUDE) as latitude",
+        "AVG(assets.ZLONGITUDE) as longitude",
+        "MIN(assets.Z_PK) as asset_id",
+    ];
+
+    let mut select = visible_assets(library);
+    select
+        .fields(&fields)
+        .field(library.name_field())
+        .and_where(with_location())
+        .and_where(bbox.conditions())
+        .and_where(format!("({})", scope))
+        .group_by(format!(
+            "CAST((assets.ZLATITUDE + 90) / {} AS INTEGER)",
+            cell
+        ))
+        .group_by(format!(
+            "CAST((assets.ZLONGITUDE + 180) / {} AS INTEGER)",
+            cell
+        ))
+        .order_desc("count")
+        .limit(MAX_CLUSTERS);
+
+    let records = query_as::<_, Cluster>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{Connection, NO_PARAMS};
+
+    fn select(conn: &Connection, sql: &str) -> Vec<i32> {
+        let mut statement = conn.prepare(sql).unwrap();
+        let rows = statement.query_map(NO_PARAMS, |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    fn assets() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE assets (Z_PK INTEGER, ZLATITUDE REAL, ZLONGITUDE REAL, \
+            ZFAVORITE INTEGER); \
+            INSERT INTO assets VALUES (1, 10, 170, 1), (2, 10, -170, 0), (3, 10, 0, 1), \
+            (4, 50, 175, 1), (5, -180, -180, 1);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn bounding_box_crosses_the_antimeridian() {
+        let conn = assets();
+        let bbox = BoundingBox {
+            south: 0.0,
+            west: 160.0,
+            north: 20.0,
+            east: -160.0,
+        };
+        let sql = format!(
+            "SELECT Z_PK FROM assets WHERE {} AND {} ORDER BY Z_PK",
+            with_location(),
+            bbox.conditions()
+        );
+
+        assert_eq!(select(&conn, &sql), vec![1, 2]);
+    }
+
+    #[test]
+    fn bounding_box_keeps_other_conditions() {
+        let conn = assets();
+        let bbox = BoundingBox {
+            south: -90.0,
+            west: 160.0,
+            north: 90.0,
+            east: -160.0,
+        };
+        let sql = format!(
+            "SELECT Z_PK FROM assets WHERE {} AND {} AND assets.ZFAVORITE = 1 ORDER BY Z_PK",
+            bbox.conditions(),
+            with_location()
+        );
+
+        assert_eq!(select(&conn, &sql), vec![1, 4]);
+    }
+
+    #[test]
+    fn rejects_non_positive_radius() {
+        assert!(near(10.0, 10.0, 0.0).is_err());
+        assert!(near(10.0, 10.0, 1000.0).is_ok());
+    }
+}
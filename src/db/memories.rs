@@ -0,0 +1,212 @@
+use super::{
+    assets::{assets_matching, Asset, AssetFilter},
+    dates::{local_capture_date, Timestamp},
+    entities::{entity_id, Entity},
+    errors::{graphql_error, XpozError},
+    libraries::{library, Library},
+    loaders::loaders,
+    pagination::{Page, PagedConnection, MAX_PAGE_SIZE},
+};
+use anyhow::Result;
+use async_graphql::{connection::query, Context, Object, Result as AGResult};
+use chrono::NaiveDate;
+use sql_builder::prelude::*;
+use sqlx::query_as;
+
+/// A memory generated by Photos.app, such as "Summer in Lisbon"
+#[derive(sqlx::FromRow)]
+pub struct Memory {
+    pub id: i32,
+    uuid: String,
+    library: String,
+    title: Option<String>,
+    subtitle: Option<String>,
+    created_at: f64,
+    favorite: bool,
+    key_asset_id: Option<i32>,
+}
+
+#[Object]
+impl Memory {
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
+    }
+    /// Name of the library the memory belongs to
+    async fn library(&self) -> &String {
+        &self.library
+    }
+    async fn title(&self) -> &Option<String> {
+        &self.title
+    }
+    async fn subtitle(&self) -> &Option<String> {
+        &self.subtitle
+    }
+    async fn created_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.created_at)
+    }
+    async fn favorite(&self) -> &bool {
+        &self.favorite
+    }
+
+    /// The asset Photos uses as the cover of this memory
+    async fn key_asset(&self, ctx: &Context<'_>) -> AGResult<Option<Asset>> {
+        match self.key_asset_id {
+            None => Ok(None),
+            Some(id) => Ok(loaders(ctx, &self.library)?
+                .assets
+                .load_one(id)
+                .await
+                .map_err(graphql_error)?),
+        }
+    }
+
+    /// The assets Photos picked for this memory
+    async fn assets(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        filter: Option<AssetFilter>,
+    ) -> AGResult<PagedConnection<Asset>> {
+        let library = library(ctx, &self.library)?;
+        let filter = filter.unwrap_or_default();
+        let conditions = format!(
+            "assets.Z_PK IN ({})",
+            curated_assets(&library.entities(), self.id)?
+        );
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                assets_matching(
+                    library,
+                    &conditions,
+                    &filter,
+                    &Page::new(after, before, first, last),
+                )
+                .await
+                .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+}
+
+/// A subquery selecting the primary keys of the curated assets of a memory.
+/// The join table is named after the inverse relationship on ZASSET
+fn curated_assets(cache: &[Entity], memory_id: i32) -> Result<String> {
+    let asset = entity_id(cache, "Asset")?;
+    let memory = entity_id(cache, "Memory")?;
+    Ok(format!(
+        "SELECT Z_{a}CURATEDASSETS FROM Z_{a}MEMORIESBEINGCURATEDASSETS \
+        WHERE Z_{m}MEMORIESBEINGCURATEDASSETS = {id}",
+        a = asset,
+        m = memory,
+        id = memory_id
+    ))
+}
+
+fn base_select(library: &Library) -> SqlBuilder {
+    let fields = [
+        "Z_PK as id",
+        "ZUUID as uuid",
+        "ZTITLE as title",
+        "ZSUBTITLE as subtitle",
+        "ZCREATIONDATE as created_at",
+        "IFNULL(ZFAVORITE, 0) as favorite",
+        "ZKEYASSET as key_asset_id",
+    ];
+
+    let mut builder = SqlBuilder::select_from("ZMEMORY");
+    builder
+        .fields(&fields)
+        .field(library.name_field())
+        .and_where("IFNULL(ZREJECTED, 0) < 1")
+        .and_where("IFNULL(ZPENDING, 0) < 1");
+
+    builder
+}
+
+/// Memories generated by Photos, newest first
+pub async fn memories(library: &Library, favorites: bool, limit: usize) -> Result<Vec<Memory>> {
+    if limit > MAX_PAGE_SIZE {
+        return Err(XpozError::Validation(format!(
+            "Can't request more than {} records at a time",
+            MAX_PAGE_SIZE
+        ))
+        .into());
+    }
+
+    let mut select = base_select(library);
+
+    if favorites {
+        select.and_where_eq("ZFAVORITE", 1);
+    }
+
+    select
+        .order_desc("ZCREATIONDATE")
+        .order_desc("Z_PK")
+        .limit(limit);
+
+    let records = query_as::<_, Memory>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
+
+pub async fn memory(library: &Library, uuid: &str) -> Result<Option<Memory>> {
+    let mut select = base_select(library);
+    select.and_where("ZUUID = ?".bind(&uuid));
+
+    let result = query_as::<_, Memory>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+/// Sql conditions on the `assets` table matching the assets taken on the
+/// same day of the year as `date` (YYYY-MM-DD, today if missing) in any of
+/// the previous years
+pub fn on_this_day(date: Option<&str>) -> Result<String> {
+    let day = match date {
+        Some(d) => {
+            let date = NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                .map_err(|_| XpozError::Validation(format!("Invalid date {}", d)))?;
+            "date(?)".bind(&date.format("%Y-%m-%d").to_string())
+        }
+        None => "date('now','localtime')".to_string(),
+    };
+    let created = local_capture_date();
+    Ok(format!(
+        "strftime('%m-%d', {created}) = strftime('%m-%d', {day}) \
+        AND strftime('%Y', {created}) < strftime('%Y', {day})",
+        created = created,
+        day = day
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_dates() {
+        assert!(on_this_day(Some("2020-02-29"))
+            .unwrap()
+            .contains("date('2020-02-29')"));
+        assert!(on_this_day(None).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for date in &["2021-02-29", "29-02-2020", "2020-02-29') OR 1=1 --", ""] {
+            assert!(on_this_day(Some(date)).is_err());
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use super::dates::Timestamp;
+use anyhow::Result;
+use async_graphql::{dataloader::Loader, Object};
+use sql_builder::prelude::*;
+use sqlx::{query_as, sqlite::SqlitePool};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Additional attributes of an asset which are not needed for
+/// displaying album grids and are loaded on demand
+#[derive(sqlx::FromRow, Clone)]
+pub struct AssetMetadata {
+    pub asset_id: i32,
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    pub original_filename: Option<String>,
+    pub file_size: Option<i64>,
+    pub reverse_location: Option<Vec<u8>>,
+    orientation: Option<i32>,
+    camera_make: Option<String>,
+    camera_model: Option<String>,
+    lens_model: Option<String>,
+    focal_length: Option<f32>,
+    focal_length_in_35mm: Option<i32>,
+    aperture: Option<f32>,
+    iso: Option<i32>,
+    shutter_speed: Option<f32>,
+    editor: Option<String>,
+    adjustment_format: Option<String>,
+    adjusted_at: Option<f64>,
+}
+
+impl AssetMetadata {
+    pub fn adjustment(&self) -> Option<Adjustment> {
+        self.adjusted_at.map(|adjusted_at| Adjustment {
+            editor: self.editor.clone(),
+            format: self.adjustment_format.clone(),
+            adjusted_at,
+        })
+    }
+
+    pub fn exif(&self) -> Exif {
+        Exif {
+            orientation: self.orientation,
+            camera_make: self.camera_make.clone(),
+            camera_model: self.camera_model.clone(),
+            lens_model: self.lens_model.clone(),
+            focal_length: self.focal_length,
+            focal_length_in_35mm: self.focal_length_in_35mm,
+            aperture: self.aperture,
+            iso: self.iso,
+            shutter_speed: self.shutter_speed,
+        }
+    }
+}
+
+pub struct Exif {
+    orientation: Option<i32>,
+    camera_make: Option<String>,
+    camera_model: Option<String>,
+    lens_model: Option<String>,
+    focal_length: Option<f32>,
+    focal_length_in_35mm: Option<i32>,
+    aperture: Option<f32>,
+    iso: Option<i32>,
+    shutter_speed: Option<f32>,
+}
+
+#[Object]
+impl Exif {
+    async fn orientation(&self) -> &Option<i32> {
+        &self.orientation
+    }
+    async fn camera_make(&self) -> &Option<String> {
+        &self.camera_make
+    }
+    async fn camera_model(&self) -> &Option<String> {
+        &self.camera_model
+    }
+    async fn lens_model(&self) -> &Option<String> {
+        &self.lens_model
+    }
+    async fn focal_length(&self) -> &Option<f32> {
+        &self.focal_length
+    }
+    async fn focal_length_in_35mm(&self) -> &Option<i32> {
+        &self.focal_length_in_35mm
+    }
+    async fn aperture(&self) -> &Option<f32> {
+        &self.aperture
+    }
+    async fn iso(&self) -> &Option<i32> {
+        &self.iso
+    }
+    /// Exposure time in seconds
+    async fn shutter_speed(&self) -> &Option<f32> {
+        &self.shutter_speed
+    }
+}
+
+/// The latest edit made to an asset
+pub struct Adjustment {
+    editor: Option<String>,
+    format: Option<String>,
+    adjusted_at: f64,
+}
+
+#[Object]
+impl Adjustment {
+    /// Name of the app the asset was edited with, such as "Photos"
+    async fn editor(&self) -> &Option<String> {
+        &self.editor
+    }
+    /// Identifier of the format the adjustments are stored in, such as
+    /// "com.apple.photo"
+    async fn format(&self) -> &Option<String> {
+        &self.format
+    }
+    async fn adjusted_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.adjusted_at)
+    }
+}
+
+fn base_select() -> SqlBuilder {
+    let fields = [
+        "attrs.ZASSET as asset_id",
+        "attrs.ZTITLE as title",
+        "descr.ZLONGDESCRIPTION as caption",
+        "attrs.ZORIGINALFILENAME as original_filename",
+        "attrs.ZORIGINALFILESIZE as file_size",
+        "attrs.ZREVERSELOCATIONDATA as reverse_location",
+        "attrs.ZORIGINALORIENTATION as orientation",
+        "ext.ZCAMERAMAKE as camera_make",
+        "ext.ZCAMERAMODEL as camera_model",
+        "ext.ZLENSMODEL as lens_model",
+        "ext.ZFOCALLENGTH as focal_length",
+        "ext.ZFOCALLENGTHIN35MM as focal_length_in_35mm",
+        "ext.ZAPERTURE as aperture",
+        "ext.ZISO as iso",
+        "ext.ZSHUTTERSPEED as shutter_speed",
+        "adj.ZEDITORLOCALIZEDNAME as editor",
+        "adj.ZADJUSTMENTFORMATIDENTIFIER as adjustment_format",
+        "adj.ZADJUSTMENTTIMESTAMP as adjusted_at",
+    ];
+
+    let mut builder = SqlBuilder::select_from("ZADDITIONALASSETATTRIBUTES as attrs");
+
+    builder
+        .fields(&fields)
+        .left()
+        .join("ZEXTENDEDATTRIBUTES as ext")
+        .on("ext.ZASSET = attrs.ZASSET")
+        .left()
+        .join("ZASSETDESCRIPTION as descr")
+        .on("descr.ZASSETATTRIBUTES = attrs.Z_PK")
+        .left()
+        .join("ZUNMANAGEDADJUSTMENT as adj")
+        .on("adj.Z_PK = attrs.ZUNMANAGEDADJUSTMENT");
+
+    builder
+}
+
+pub async fn metadata_by_asset_id(pool: &SqlitePool, ids: &[i32]) -> Result<Vec<AssetMetadata>> {
+    let mut select = base_select();
+    select.and_where_in("attrs.ZASSET", ids);
+
+    let records = query_as::<_, AssetMetadata>(select.sql()?.as_str())
+        .fetch_all(pool)
+        .await?;
+
+    Ok(records)
+}
+
+/// Batches metadata lookups for all assets resolved in a single request
+pub struct MetadataLoader {
+    pool: SqlitePool,
+}
+
+impl MetadataLoader {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i32> for MetadataLoader {
+    type Value = AssetMetadata;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        let records = metadata_by_asset_id(&self.pool, keys)
+            .await
+            .map_err(Arc::new)?;
+
+        Ok(records.into_iter().map(|m| (m.asset_id, m)).collect())
+    }
+}
@@ -1,9 +1,12 @@
-use rusqlite::Connection;
 use refinery::embed_migrations;
+use rusqlite::Connection;
 
 embed_migrations!();
 
 pub fn migrate_database(db: &str) {
-    let mut conn = Connection::open(db).expect(&format!("Failed to open database {} for migration", db));
-    migrations::runner().run(&mut conn).expect("Failed while trying to run migrations");
+    let mut conn = Connection::open(db)
+        .unwrap_or_else(|_| panic!("Failed to open database {} for migration", db));
+    migrations::runner()
+        .run(&mut conn)
+        .expect("Failed while trying to run migrations");
 }
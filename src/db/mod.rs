@@ -1,15 +1,52 @@
 mod albums;
+mod archive;
 pub mod assets;
+pub mod dates;
 pub mod entities;
+pub mod errors;
+mod folders;
+mod keywords;
+pub mod libraries;
+pub mod loaders;
+mod map;
+mod memories;
+pub mod metadata;
 pub mod migrate;
+pub mod model;
+pub mod pagination;
+mod people;
+pub mod permissions;
+mod places;
+pub mod reload;
+pub mod resources;
+pub mod search;
+mod smart_albums;
+mod timeline;
 pub mod tokens;
+pub mod whitelist;
 
-use albums::{album, my_albums, Album};
+use albums::{album, my_albums, shared_albums, Album};
+use assets::{asset_scope, assets_matching, Asset, AssetFilter};
 use async_graphql::{
-    Context, EmptySubscription, Error, ErrorExtensions, Object, Result, Schema as AGSchema,
+    connection::query, guard::Guard, Context, EmptySubscription, ErrorExtensions, Object, Result,
+    Schema as AGSchema,
 };
 use entities::Entity;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use errors::{graphql_error, XpozError};
+use folders::{folder, root_folder, Folder};
+use keywords::{keyword, keywords, Keyword};
+use libraries::Libraries;
+use map::{clusters, near, BoundingBox, Cluster};
+use memories::{memories, memory, on_this_day, Memory};
+use pagination::{Page, PagedConnection};
+use people::{people, person, Person};
+use permissions::{Permission, PermissionGuard};
+use search::{search, SearchConnection};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+    Executor,
+};
+use timeline::{timeline, timeline_scope, Bucket, Granularity};
 use tokens::{create_token, delete_token, tokens, update_token, Token, TokenInput};
 
 pub async fn build_pool(options: SqliteConnectOptions) -> SqlitePool {
@@ -22,13 +59,31 @@ pub async fn build_pool(options: SqliteConnectOptions) -> SqlitePool {
         .expect("Can't open database")
 }
 
-pub fn bool_to_insert_string(value: bool) -> String {
-    if value { "1" } else { "0" }.to_string()
+/// Opens the Photos database with the app database attached as `xpoz`,
+/// so that queries can join Photos records with the search index
+pub async fn build_photos_pool(options: SqliteConnectOptions, app_database: &str) -> SqlitePool {
+    log::debug!("Conn settings: {:?}", &options);
+    let attach = format!(
+        "ATTACH DATABASE {} AS xpoz",
+        sql_builder::quote(app_database)
+    );
+    SqlitePoolOptions::new()
+        .idle_timeout(std::time::Duration::new(5, 0))
+        .max_connections(3)
+        .after_connect(move |conn| {
+            let attach = attach.clone();
+            Box::pin(async move {
+                conn.execute(attach.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect_with(options)
+        .await
+        .expect("Can't open database")
 }
 
 #[derive(Clone)]
 pub struct Databases {
-    pub photos: SqlitePool,
     pub app: SqlitePool,
 }
 
@@ -37,27 +92,271 @@ pub struct QueryRoot;
 #[Object]
 impl QueryRoot {
     /// Get an album by its id
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
     async fn album(&self, ctx: &Context<'_>, id: String) -> Result<Option<Album>> {
-        album(
-            &ctx.data::<Databases>()?.photos,
-            ctx.data::<Vec<Entity>>()?,
-            &ctx.data::<Token>()?.whitelist(),
-            &id,
+        let (library, uuid) = ctx
+            .data::<Libraries>()?
+            .resolve(&id)
+            .map_err(graphql_error)?;
+        album(library, &ctx.data::<Token>()?.whitelist_for(library), uuid)
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// "My Albums" which have been xpozed, keeping the original Photos sorting
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn my_albums(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<PagedConnection<Album>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        let whitelist = ctx.data::<Token>()?.whitelist_for(library);
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                my_albums(library, &whitelist, &Page::new(after, before, first, last))
+                    .await
+                    .map_err(graphql_error)
+            },
         )
         .await
-        .map_err(Error::from)
     }
 
-    /// "My Albums" which have been xpozed, keeping the original Photos sorting
-    async fn my_albums(&self, ctx: &Context<'_>, page: Option<i32>) -> Result<Vec<Album>> {
-        my_albums(
-            &ctx.data::<Databases>()?.photos,
-            ctx.data::<Vec<Entity>>()?,
-            &ctx.data::<Token>()?.whitelist(),
-            page,
+    /// iCloud shared albums which have been xpozed, keeping the original
+    /// Photos sorting
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn shared_albums(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> Result<PagedConnection<Album>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        let whitelist = ctx.data::<Token>()?.whitelist_for(library);
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                shared_albums(library, &whitelist, &Page::new(after, before, first, last))
+                    .await
+                    .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+
+    /// Get a folder by its id
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn folder(&self, ctx: &Context<'_>, id: String) -> Result<Option<Folder>> {
+        let (library, uuid) = ctx
+            .data::<Libraries>()?
+            .resolve(&id)
+            .map_err(graphql_error)?;
+        folder(library, &ctx.data::<Token>()?.whitelist_for(library), uuid)
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// The top level folder of the Photos library
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn root_folder(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+    ) -> Result<Option<Folder>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        root_folder(library, &ctx.data::<Token>()?.whitelist_for(library))
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// People recognised by Photos, most photographed first
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn people(&self, ctx: &Context<'_>, library: Option<String>) -> Result<Vec<Person>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        people(library, &ctx.data::<Token>()?.whitelist_for(library))
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// Get a person by their id
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn person(&self, ctx: &Context<'_>, id: String) -> Result<Option<Person>> {
+        let (library, uuid) = ctx
+            .data::<Libraries>()?
+            .resolve(&id)
+            .map_err(graphql_error)?;
+        person(library, &ctx.data::<Token>()?.whitelist_for(library), uuid)
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// Keywords assigned to assets in Photos, sorted by title
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn keywords(&self, ctx: &Context<'_>, library: Option<String>) -> Result<Vec<Keyword>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        keywords(library, &ctx.data::<Token>()?.whitelist_for(library))
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// Get a keyword by its id
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn keyword(&self, ctx: &Context<'_>, id: String) -> Result<Option<Keyword>> {
+        let (library, uuid) = ctx
+            .data::<Libraries>()?
+            .resolve(&id)
+            .map_err(graphql_error)?;
+        keyword(library, &ctx.data::<Token>()?.whitelist_for(library), uuid)
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// The whole library grouped by capture date, newest first. Only
+    /// available to owners and tokens with access to date ranges
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn timeline(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+        granularity: Granularity,
+    ) -> Result<Vec<Bucket>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        match timeline_scope(ctx.data::<Token>()?) {
+            Some(scope) => timeline(library, &scope, granularity)
+                .await
+                .map_err(graphql_error),
+            None => Err(XpozError::Forbidden.extend()),
+        }
+    }
+
+    /// Assets within a bounding box, clustered for the given map zoom level
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn places(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+        bbox: BoundingBox,
+        zoom: i32,
+    ) -> Result<Vec<Cluster>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        let scope = asset_scope(library, &ctx.data::<Token>()?.whitelist_for(library))
+            .map_err(graphql_error)?;
+        clusters(library, &scope, &bbox, zoom)
+            .await
+            .map_err(graphql_error)
+    }
+
+    /// Assets taken within `radius` meters of a location
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn assets_near(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+        latitude: f64,
+        longitude: f64,
+        radius: f64,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        filter: Option<AssetFilter>,
+    ) -> Result<PagedConnection<Asset>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        let scope = asset_scope(library, &ctx.data::<Token>()?.whitelist_for(library))
+            .map_err(graphql_error)?;
+        let nearby = near(latitude, longitude, radius).map_err(graphql_error)?;
+        let conditions = format!("({}) AND ({})", scope, nearby);
+        let filter = filter.unwrap_or_default();
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let page = Page::new(after, before, first, last);
+                assets_matching(library, &conditions, &filter, &page)
+                    .await
+                    .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+
+    /// Albums and assets of all libraries with titles, captions, keywords,
+    /// people or places matching the search query, best matches first
+    #[graphql(guard(PermissionGuard(permission = "Permission::Browse")))]
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> Result<SearchConnection> {
+        let after = match after {
+            Some(cursor) => Some(
+                cursor
+                    .parse::<usize>()
+                    .map_err(|_| XpozError::Validation("Invalid cursor".to_string()).extend())?,
+            ),
+            None => None,
+        };
+        search(
+            ctx.data::<Libraries>()?,
+            ctx.data::<Token>()?,
+            &query,
+            after,
+            first.map(|f| f.max(0) as usize),
         )
         .await
-        .map_err(Error::from)
+        .map_err(graphql_error)
+    }
+
+    /// Names of the Photos libraries being served, the default one first
+    async fn libraries(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        Ok(ctx
+            .data::<Libraries>()?
+            .iter()
+            .map(|l| l.name.clone())
+            .collect())
     }
 
     /// Returns the current access token
@@ -66,18 +365,79 @@ impl QueryRoot {
         Ok(token_ref.clone())
     }
 
-    // Admin resources
+    // Resources outside of any whitelist
+
+    /// Memories generated by Photos, newest first
+    #[graphql(guard(PermissionGuard(permission = "Permission::BrowseAll")))]
+    async fn memories(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+        #[graphql(default)] favorites: bool,
+        #[graphql(default = 20)] limit: i32,
+    ) -> Result<Vec<Memory>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        memories(library, favorites, limit.max(0) as usize)
+            .await
+            .map_err(graphql_error)
+    }
 
-    /// Returns the available access tokens
+    /// Get a memory by its id
+    #[graphql(guard(PermissionGuard(permission = "Permission::BrowseAll")))]
+    async fn memory(&self, ctx: &Context<'_>, id: String) -> Result<Option<Memory>> {
+        let (library, uuid) = ctx
+            .data::<Libraries>()?
+            .resolve(&id)
+            .map_err(graphql_error)?;
+        memory(library, uuid).await.map_err(graphql_error)
+    }
+
+    /// Assets taken on this day of the year in previous years. `date` is
+    /// formatted as YYYY-MM-DD and defaults to today
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(guard(PermissionGuard(permission = "Permission::BrowseAll")))]
+    async fn on_this_day(
+        &self,
+        ctx: &Context<'_>,
+        library: Option<String>,
+        date: Option<String>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        filter: Option<AssetFilter>,
+    ) -> Result<PagedConnection<Asset>> {
+        let library = ctx
+            .data::<Libraries>()?
+            .get(library.as_deref())
+            .map_err(graphql_error)?;
+        let conditions = on_this_day(date.as_deref()).map_err(graphql_error)?;
+        let filter = filter.unwrap_or_default();
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let page = Page::new(after, before, first, last);
+                assets_matching(library, &conditions, &filter, &page)
+                    .await
+                    .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+
+    /// Returns the available access tokens. Curators only get the ones
+    /// they've created
+    #[graphql(guard(PermissionGuard(permission = "Permission::ShareAlbums")))]
     async fn tokens(&self, ctx: &Context<'_>) -> Result<Vec<Token>> {
-        let token = ctx.data::<Token>()?;
-        if token.admin {
-            tokens(&ctx.data::<Databases>()?.app)
-                .await
-                .map_err(Error::from)
-        } else {
-            Err(Error::new("Unauthorised").extend_with(|_, e| e.set("code", 401)))
-        }
+        tokens(&ctx.data::<Databases>()?.app, ctx.data::<Token>()?)
+            .await
+            .map_err(graphql_error)
     }
 }
 
@@ -85,42 +445,37 @@ pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
+    #[graphql(guard(PermissionGuard(permission = "Permission::ShareAlbums")))]
     async fn create_token(&self, ctx: &Context<'_>, input: TokenInput) -> Result<Option<Token>> {
-        let token = ctx.data::<Token>()?;
-        if token.admin {
-            create_token(&ctx.data::<Databases>()?.app, input)
-                .await
-                .map_err(Error::from)
-        } else {
-            Err(Error::new("Unauthorised").extend_with(|_, e| e.set("code", 401)))
-        }
+        input.authorize(ctx).await?;
+        create_token(&ctx.data::<Databases>()?.app, ctx.data::<Token>()?, input)
+            .await
+            .map_err(graphql_error)
     }
 
+    #[graphql(guard(PermissionGuard(permission = "Permission::ShareAlbums")))]
     async fn update_token(
         &self,
         ctx: &Context<'_>,
         id: String,
         input: TokenInput,
     ) -> Result<Option<Token>> {
-        let token = ctx.data::<Token>()?;
-        if token.admin {
-            update_token(&ctx.data::<Databases>()?.app, &id, input)
-                .await
-                .map_err(Error::from)
-        } else {
-            Err(Error::new("Unauthorised").extend_with(|_, e| e.set("code", 401)))
-        }
+        input.authorize(ctx).await?;
+        update_token(
+            &ctx.data::<Databases>()?.app,
+            ctx.data::<Token>()?,
+            &id,
+            input,
+        )
+        .await
+        .map_err(graphql_error)
     }
 
+    #[graphql(guard(PermissionGuard(permission = "Permission::ShareAlbums")))]
     async fn delete_token(&self, ctx: &Context<'_>, id: String) -> Result<Option<Token>> {
-        let token = ctx.data::<Token>()?;
-        if token.admin {
-            delete_token(&ctx.data::<Databases>()?.app, id)
-                .await
-                .map_err(Error::from)
-        } else {
-            Err(Error::new("Unauthorised").extend_with(|_, e| e.set("code", 401)))
-        }
+        delete_token(&ctx.data::<Databases>()?.app, ctx.data::<Token>()?, id)
+            .await
+            .map_err(graphql_error)
     }
 }
 
@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Result};
+use plist::Value;
+use sql_builder::prelude::*;
+use sqlx::{query_as, sqlite::SqlitePool};
+use std::sync::{Arc, RwLock};
+
+// Ranges of the model versions Photos.app records in Z_METADATA, along with
+// the releases of macOS shipping them. Only the versions the queries were
+// verified against are supported. Older and newer databases rename tables
+// and columns (ZGENERICASSET, the relationships of ZDETECTEDFACE) and change
+// the ones albums, moments and memories are read from (ZKINDSUBTYPE,
+// ZDEPTHTYPE, ...), which would need mapping here before they can be supported
+const SUPPORTED_VERSIONS: [(i64, i64, &str); 2] =
+    [(14000, 14999, "macOS 11"), (15000, 15999, "macOS 12")];
+
+/// Names of the tables and columns of the Photos database which have
+/// changed between releases of macOS
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Model {
+    pub version: i64,
+    /// Table of assets, selected from as `assets`
+    pub assets: &'static str,
+    /// Column of ZDETECTEDFACE referencing the asset a face is on
+    pub face_asset: &'static str,
+    /// Column of ZDETECTEDFACE referencing the person a face belongs to
+    pub face_person: &'static str,
+    /// Expression selecting the cover chosen by the user for an album,
+    /// NULL when the database doesn't keep one
+    pub custom_key_asset: &'static str,
+}
+
+impl Model {
+    fn new(version: i64, custom_key_asset: bool) -> Result<Self> {
+        if !SUPPORTED_VERSIONS
+            .iter()
+            .any(|(from, to, _)| (*from..=*to).contains(&version))
+        {
+            let supported: Vec<String> = SUPPORTED_VERSIONS
+                .iter()
+                .map(|(from, to, release)| format!("{}-{} ({})", from, to, release))
+                .collect();
+            return Err(anyhow!(
+                "Photos database model version {} is not supported. Supported versions are {}",
+                version,
+                supported.join(", ")
+            ));
+        }
+
+        Ok(Self {
+            version,
+            assets: "ZASSET",
+            face_asset: "ZASSET",
+            face_person: "ZPERSON",
+            custom_key_asset: if custom_key_asset {
+                "ZCUSTOMKEYASSET"
+            } else {
+                "NULL"
+            },
+        })
+    }
+
+    /// A subquery selecting the primary keys of the assets the people
+    /// matching the sql conditions on the `people` table were recognised on
+    pub fn person_assets(&self, conditions: &str) -> String {
+        format!(
+            "SELECT faces.{asset} FROM ZDETECTEDFACE faces \
+            JOIN ZPERSON people ON people.Z_PK = faces.{person} \
+            WHERE {conditions}",
+            asset = self.face_asset,
+            person = self.face_person,
+            conditions = conditions
+        )
+    }
+}
+
+async fn model_version(pool: &SqlitePool) -> Result<i64> {
+    let mut select = SqlBuilder::select_from("Z_METADATA");
+    select.field("Z_PLIST").order_desc("Z_VERSION").limit(1);
+
+    let (blob,) = query_as::<_, (Vec<u8>,)>(select.sql()?.as_str())
+        .fetch_one(pool)
+        .await?;
+
+    Value::from_reader(std::io::Cursor::new(blob))?
+        .as_dictionary()
+        .and_then(|metadata| metadata.get("PLModelVersion"))
+        .and_then(Value::as_signed_integer)
+        .ok_or_else(|| anyhow!("Can't find the model version of the Photos database"))
+}
+
+async fn has_column(pool: &SqlitePool, table: &str, column: &str) -> Result<bool> {
+    let mut select = SqlBuilder::select_from(format!("pragma_table_info({})", quote(table)));
+    select.field("COUNT(*)").and_where_eq("name", quote(column));
+
+    let (count,) = query_as::<_, (i64,)>(select.sql()?.as_str())
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count > 0)
+}
+
+/// Detects the version of a Photos database, failing for versions whose
+/// layout isn't known
+pub async fn detect_model(pool: &SqlitePool) -> Result<Model> {
+    let version = model_version(pool).await?;
+    let custom_key_asset = has_column(pool, "ZGENERICALBUM", "ZCUSTOMKEYASSET").await?;
+    Model::new(version, custom_key_asset)
+}
+
+/// The model of a library. Photos.app migrates its database when the OS is
+/// updated, so the model is detected again along with the entities
+#[derive(Clone)]
+pub struct ModelCache(Arc<RwLock<Model>>);
+
+impl ModelCache {
+    pub fn new(model: Model) -> Self {
+        Self(Arc::new(RwLock::new(model)))
+    }
+
+    pub fn get(&self) -> Model {
+        *self.0.read().expect("Model cache lock is poisoned")
+    }
+
+    /// Replaces the cached model, returning whether it has changed
+    pub fn replace(&self, model: Model) -> bool {
+        let mut cache = self.0.write().expect("Model cache lock is poisoned");
+        if *cache == model {
+            return false;
+        }
+        *cache = model;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_the_layout_of_macos_11_and_12() {
+        for version in &[14000, 15999] {
+            let model = Model::new(*version, true).unwrap();
+            assert_eq!(model.assets, "ZASSET");
+            assert_eq!(model.face_asset, "ZASSET");
+            assert_eq!(model.face_person, "ZPERSON");
+            assert_eq!(model.custom_key_asset, "ZCUSTOMKEYASSET");
+        }
+    }
+
+    #[test]
+    fn selects_no_cover_without_the_column() {
+        assert_eq!(Model::new(14000, false).unwrap().custom_key_asset, "NULL");
+    }
+
+    #[test]
+    fn rejects_unverified_versions() {
+        assert!(Model::new(13999, false).is_err());
+        assert!(Model::new(16000, true).is_err());
+    }
+
+    #[test]
+    fn replaces_changed_models() {
+        let cache = ModelCache::new(Model::new(15000, true).unwrap());
+        assert!(!cache.replace(Model::new(15000, true).unwrap()));
+        assert!(cache.replace(Model::new(15000, false).unwrap()));
+        assert_eq!(cache.get().custom_key_asset, "NULL");
+    }
+}
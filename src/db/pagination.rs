@@ -0,0 +1,235 @@
+use super::errors::XpozError;
+use anyhow::{Error, Result};
+use async_graphql::{
+    connection::{Connection, CursorType, Edge, EmptyFields},
+    SimpleObject,
+};
+use sql_builder::prelude::*;
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Position of a record in a list ordered by one of the Photos ordering
+/// keys (`Z_FOK_*`), with the primary key as a tiebreaker
+#[derive(Clone, Copy)]
+pub struct SortCursor {
+    pub key: i64,
+    pub id: i32,
+}
+
+impl CursorType for SortCursor {
+    type Error = Error;
+
+    fn decode_cursor(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, ':');
+        let key = parts.next().and_then(|v| v.parse().ok());
+        let id = parts.next().and_then(|v| v.parse().ok());
+        match (key, id) {
+            (Some(key), Some(id)) => Ok(Self { key, id }),
+            _ => Err(XpozError::Validation("Invalid cursor".to_string()).into()),
+        }
+    }
+
+    fn encode_cursor(&self) -> String {
+        format!("{}:{}", self.key, self.id)
+    }
+}
+
+/// Records which can be paginated with a `SortCursor`
+pub trait Sorted {
+    fn cursor(&self) -> SortCursor;
+}
+
+#[derive(SimpleObject)]
+pub struct ConnectionFields {
+    pub total_count: i64,
+}
+
+pub type PagedConnection<T> = Connection<SortCursor, T, ConnectionFields, EmptyFields>;
+
+/// The relay pagination arguments of a connection field
+pub struct Page {
+    pub after: Option<SortCursor>,
+    pub before: Option<SortCursor>,
+    pub first: Option<usize>,
+    pub last: Option<usize>,
+}
+
+impl Page {
+    pub fn new(
+        after: Option<SortCursor>,
+        before: Option<SortCursor>,
+        first: Option<usize>,
+        last: Option<usize>,
+    ) -> Self {
+        Self {
+            after,
+            before,
+            first,
+            last,
+        }
+    }
+
+    fn backwards(&self) -> bool {
+        self.first.is_none() && self.last.is_some()
+    }
+
+    fn size(&self) -> Result<usize> {
+        let size = self.first.or(self.last).unwrap_or(DEFAULT_PAGE_SIZE);
+        if size > MAX_PAGE_SIZE {
+            return Err(XpozError::Validation(format!(
+                "Can't request more than {} records at a time",
+                MAX_PAGE_SIZE
+            ))
+            .into());
+        }
+        Ok(size)
+    }
+
+    /// Narrows down a select to the requested page. `key` and `id` are the
+    /// sql expressions the records are ordered by
+    pub fn apply(&self, builder: &mut SqlBuilder, key: &str, id: &str) -> Result<()> {
+        if let Some(after) = self.after {
+            builder.and_where(
+                format!(
+                    "({key} > ? OR ({key} = ? AND {id} > ?))",
+                    key = key,
+                    id = id
+                )
+                .bind(&after.key)
+                .bind(&after.key)
+                .bind(&after.id),
+            );
+        }
+
+        if let Some(before) = self.before {
+            builder.and_where(
+                format!(
+                    "({key} < ? OR ({key} = ? AND {id} < ?))",
+                    key = key,
+                    id = id
+                )
+                .bind(&before.key)
+                .bind(&before.key)
+                .bind(&before.id),
+            );
+        }
+
+        if self.backwards() {
+            builder.order_desc(key).order_desc(id);
+        } else {
+            builder.order_asc(key).order_asc(id);
+        }
+
+        // fetch an extra record to find out if there are more pages
+        builder.limit(self.size()? + 1);
+
+        Ok(())
+    }
+
+    /// Builds a connection out of the records fetched with a select
+    /// narrowed down by `apply`
+    pub fn connection<T: Sorted>(
+        &self,
+        mut records: Vec<T>,
+        total_count: i64,
+    ) -> Result<PagedConnection<T>> {
+        let size = self.size()?;
+        let has_more = records.len() > size;
+        records.truncate(size);
+
+        let (has_previous_page, has_next_page) = if self.backwards() {
+            records.reverse();
+            (has_more, self.before.is_some())
+        } else {
+            (self.after.is_some(), has_more)
+        };
+
+        let mut connection = Connection::with_additional_fields(
+            has_previous_page,
+            has_next_page,
+            ConnectionFields { total_count },
+        );
+        connection.append(
+            records
+                .into_iter()
+                .map(|record| Edge::new(record.cursor(), record)),
+        );
+
+        Ok(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{Connection, NO_PARAMS};
+
+    fn cursor(key: i64, id: i32) -> Option<SortCursor> {
+        Some(SortCursor { key, id })
+    }
+
+    // Pages through a table of albums, only half of which are shared
+    fn shared_ids(page: Page) -> Vec<i32> {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE albums (id INTEGER, sort_key INTEGER, shared INTEGER); \
+            INSERT INTO albums VALUES (1, 10, 1), (2, 20, 0), (3, 20, 1), (4, 30, 0), (5, 40, 1);",
+        )
+        .unwrap();
+
+        let mut select = SqlBuilder::select_from("albums");
+        select.field("id").and_where_eq("shared", 1);
+        page.apply(&mut select, "sort_key", "id").unwrap();
+
+        let mut statement = conn.prepare(&select.sql().unwrap()).unwrap();
+        let rows = statement.query_map(NO_PARAMS, |row| row.get(0)).unwrap();
+        rows.map(|id| id.unwrap()).collect()
+    }
+
+    #[test]
+    fn pages_forwards_within_the_select() {
+        let page = Page::new(cursor(10, 1), None, Some(10), None);
+        assert_eq!(shared_ids(page), vec![3, 5]);
+    }
+
+    #[test]
+    fn pages_backwards_within_the_select() {
+        let page = Page::new(None, cursor(40, 5), None, Some(10));
+        assert_eq!(shared_ids(page), vec![3, 1]);
+    }
+
+    #[test]
+    fn breaks_ties_on_the_id() {
+        let page = Page::new(cursor(20, 2), None, Some(10), None);
+        assert_eq!(shared_ids(page), vec![3, 5]);
+    }
+
+    #[test]
+    fn fetches_an_extra_record() {
+        let page = Page::new(None, None, Some(1), None);
+        assert_eq!(shared_ids(page), vec![1, 3]);
+    }
+
+    #[test]
+    fn rejects_large_pages() {
+        let page = Page::new(None, None, Some(MAX_PAGE_SIZE + 1), None);
+        let mut select = SqlBuilder::select_from("albums");
+        assert!(page.apply(&mut select, "sort_key", "id").is_err());
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = SortCursor { key: -42, id: 7 };
+        let decoded = SortCursor::decode_cursor(&cursor.encode_cursor()).unwrap();
+        assert_eq!((decoded.key, decoded.id), (-42, 7));
+    }
+
+    #[test]
+    fn rejects_invalid_cursors() {
+        assert!(SortCursor::decode_cursor("").is_err());
+        assert!(SortCursor::decode_cursor("12").is_err());
+        assert!(SortCursor::decode_cursor("a:1").is_err());
+        assert!(SortCursor::decode_cursor("1:b").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use super::{
+    albums::AllowedAlbumIds,
+    assets::{assets_matching, Asset, AssetFilter},
+    errors::graphql_error,
+    libraries::{library, Library},
+    loaders::loaders,
+    pagination::{Page, PagedConnection},
+    tokens::Token,
+};
+use anyhow::Result;
+use async_graphql::{connection::query, dataloader::Loader, Context, Object, Result as AGResult};
+use sql_builder::prelude::*;
+use sqlx::query_as;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(sqlx::FromRow)]
+pub struct Person {
+    pub id: i32,
+    uuid: String,
+    library: String,
+    name: String,
+    display_name: Option<String>,
+    face_count: i32,
+    key_face_id: Option<i32>,
+}
+
+#[Object]
+impl Person {
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
+    }
+    /// Name of the library the person belongs to
+    async fn library(&self) -> &String {
+        &self.library
+    }
+    async fn name(&self) -> &String {
+        &self.name
+    }
+    async fn display_name(&self) -> &Option<String> {
+        &self.display_name
+    }
+    async fn face_count(&self) -> &i32 {
+        &self.face_count
+    }
+
+    /// The face Photos uses to represent this person
+    async fn key_face(&self, ctx: &Context<'_>) -> AGResult<Option<Face>> {
+        match self.key_face_id {
+            None => Ok(None),
+            Some(id) => Ok(face(library(ctx, &self.library)?, id)
+                .await
+                .map_err(graphql_error)?),
+        }
+    }
+
+    async fn assets(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        filter: Option<AssetFilter>,
+    ) -> AGResult<PagedConnection<Asset>> {
+        let library = library(ctx, &self.library)?;
+        let filter = filter.unwrap_or_default();
+        let conditions = format!(
+            "assets.Z_PK IN ({})",
+            library
+                .model()
+                .person_assets(&format!("people.Z_PK = {}", self.id))
+        );
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                assets_matching(
+                    library,
+                    &conditions,
+                    &filter,
+                    &Page::new(after, before, first, last),
+                )
+                .await
+                .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+}
+
+/// A face detected on an asset. The coordinates are relative to
+/// the dimensions of the asset, with the origin at the bottom left
+#[derive(sqlx::FromRow, Clone)]
+pub struct Face {
+    library: String,
+    asset_id: i32,
+    person_id: Option<i32>,
+    center_x: f32,
+    center_y: f32,
+    size: f32,
+}
+
+#[Object]
+impl Face {
+    async fn center_x(&self) -> &f32 {
+        &self.center_x
+    }
+    async fn center_y(&self) -> &f32 {
+        &self.center_y
+    }
+    async fn size(&self) -> &f32 {
+        &self.size
+    }
+    async fn person(&self, ctx: &Context<'_>) -> AGResult<Option<Person>> {
+        let library = library(ctx, &self.library)?;
+        match self.person_id {
+            None => Ok(None),
+            Some(id) => Ok(
+                person_by_id(library, &ctx.data::<Token>()?.whitelist_for(library), id)
+                    .await
+                    .map_err(graphql_error)?,
+            ),
+        }
+    }
+    async fn asset(&self, ctx: &Context<'_>) -> AGResult<Option<Asset>> {
+        Ok(loaders(ctx, &self.library)?
+            .assets
+            .load_one(self.asset_id)
+            .await
+            .map_err(graphql_error)?)
+    }
+}
+
+fn base_select(library: &Library, whitelist: &AllowedAlbumIds) -> SqlBuilder {
+    let fields = [
+        "Z_PK as id",
+        "ZPERSONUUID as uuid",
+        "ZFULLNAME as name",
+        "ZDISPLAYNAME as display_name",
+        "ZFACECOUNT as face_count",
+        "ZKEYFACE as key_face_id",
+    ];
+
+    let mut builder = SqlBuilder::select_from("ZPERSON");
+    builder
+        .fields(&fields)
+        .field(library.name_field())
+        .and_where_is_not_null("ZFULLNAME")
+        .and_where_ne("ZFULLNAME", quote(""))
+        .and_where_gt("ZFACECOUNT", 0);
+
+    if let Some(wl) = whitelist {
+        builder.and_where_in_quoted("ZPERSONUUID", &wl.people);
+    }
+
+    builder
+}
+
+pub async fn people(library: &Library, whitelist: &AllowedAlbumIds) -> Result<Vec<Person>> {
+    let mut select = base_select(library, whitelist);
+    select.order_desc("ZFACECOUNT").order_asc("ZFULLNAME");
+
+    let records = query_as::<_, Person>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
+
+pub async fn person(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    uuid: &str,
+) -> Result<Option<Person>> {
+    let mut select = base_select(library, whitelist);
+    select.and_where("ZPERSONUUID = ?".bind(&uuid));
+
+    let result = query_as::<_, Person>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+pub async fn person_by_id(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    id: i32,
+) -> Result<Option<Person>> {
+    let mut select = base_select(library, whitelist);
+    select.and_where_eq("Z_PK", id);
+
+    let result = query_as::<_, Person>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+fn faces_select(library: &Library) -> SqlBuilder {
+    let fields = [
+        "Z_PK as id",
+        "ZCENTERX as center_x",
+        "ZCENTERY as center_y",
+        "ZSIZE as size",
+    ];
+
+    let mut builder = SqlBuilder::select_from("ZDETECTEDFACE");
+    builder
+        .fields(&fields)
+        .field(format!("{} as asset_id", library.model().face_asset))
+        .field(format!("{} as person_id", library.model().face_person))
+        .field(library.name_field())
+        .and_where_lt("ZHIDDEN", 1);
+
+    builder
+}
+
+async fn face(library: &Library, id: i32) -> Result<Option<Face>> {
+    let mut select = faces_select(library);
+    select.and_where_eq("Z_PK", id);
+
+    let result = query_as::<_, Face>(select.sql()?.as_str())
+        .fetch_optional(&library.pool)
+        .await?;
+
+    Ok(result)
+}
+
+/// The faces of the people recognised on the assets with the given
+/// primary keys, grouped by asset
+pub async fn faces_of_assets(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    asset_ids: &[i32],
+) -> Result<HashMap<i32, Vec<Face>>> {
+    let mut select = faces_select(library);
+    select
+        .and_where_in(library.model().face_asset, asset_ids)
+        .and_where_in_query(
+            library.model().face_person,
+            base_select(library, whitelist).set_field("Z_PK").query()?,
+        )
+        .order_asc("Z_PK");
+
+    let records = query_as::<_, Face>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    let mut faces: HashMap<i32, Vec<Face>> = HashMap::new();
+    for face in records {
+        faces.entry(face.asset_id).or_default().push(face);
+    }
+
+    Ok(faces)
+}
+
+/// Batches lookups of the faces on assets, by the primary keys of the assets
+pub struct FacesLoader {
+    library: Library,
+    whitelist: AllowedAlbumIds,
+}
+
+impl FacesLoader {
+    pub fn new(library: Library, whitelist: AllowedAlbumIds) -> Self {
+        Self { library, whitelist }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i32> for FacesLoader {
+    type Value = Vec<Face>;
+    type Error = Arc<anyhow::Error>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error> {
+        faces_of_assets(&self.library, &self.whitelist, keys)
+            .await
+            .map_err(Arc::new)
+    }
+}
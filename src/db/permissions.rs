@@ -0,0 +1,97 @@
+use super::errors::XpozError;
+use super::tokens::Token;
+use async_graphql::{guard::Guard, Context, Enum, ErrorExtensions, Result as AGResult};
+
+/// What a token is used for. Each role comes with a fixed set of permissions
+#[derive(Enum, sqlx::Type, Copy, Clone, Debug, Eq, PartialEq)]
+#[sqlx(rename_all = "lowercase")]
+pub enum Role {
+    /// Can do everything, including managing other tokens
+    Owner,
+    /// Can browse, and share the albums they can see with new viewer tokens
+    Curator,
+    /// Can browse the albums, people, etc. their whitelist allows
+    Viewer,
+    /// Can add assets, but can't browse the library
+    Uploader,
+}
+
+#[derive(Enum, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Permission {
+    /// Browse what the whitelist of the token allows
+    Browse,
+    /// Browse the whole library, including memories and the full timeline
+    BrowseAll,
+    /// Create, change and remove viewer tokens for the albums the token can
+    /// see, limited to the tokens it has created
+    ShareAlbums,
+    /// Create, change and remove any token
+    ManageTokens,
+    /// Add assets to the library
+    Upload,
+}
+
+impl Role {
+    pub fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Self::Owner => &[Browse, BrowseAll, ShareAlbums, ManageTokens, Upload],
+            Self::Curator => &[Browse, ShareAlbums],
+            Self::Viewer => &[Browse],
+            Self::Uploader => &[Upload],
+        }
+    }
+}
+
+/// Limits a field to tokens with a permission
+pub struct PermissionGuard {
+    pub permission: Permission,
+}
+
+#[async_trait::async_trait]
+impl Guard for PermissionGuard {
+    async fn check(&self, ctx: &Context<'_>) -> AGResult<()> {
+        match ctx.data_opt::<Token>() {
+            None => Err(XpozError::Unauthorized.extend()),
+            Some(token) if !token.can(self.permission) => Err(XpozError::Forbidden.extend()),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owners_can_do_everything() {
+        let permissions = Role::Owner.permissions();
+        for permission in &[
+            Permission::Browse,
+            Permission::BrowseAll,
+            Permission::ShareAlbums,
+            Permission::ManageTokens,
+            Permission::Upload,
+        ] {
+            assert!(permissions.contains(permission));
+        }
+    }
+
+    #[test]
+    fn curators_share_what_they_can_browse() {
+        assert_eq!(
+            Role::Curator.permissions(),
+            &[Permission::Browse, Permission::ShareAlbums]
+        );
+    }
+
+    #[test]
+    fn viewers_only_browse_their_whitelist() {
+        assert_eq!(Role::Viewer.permissions(), &[Permission::Browse]);
+    }
+
+    #[test]
+    fn uploaders_cant_browse() {
+        assert_eq!(Role::Uploader.permissions(), &[Permission::Upload]);
+    }
+}
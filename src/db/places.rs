@@ -0,0 +1,333 @@
+use super::{archive::unarchive, assets::visible_assets, libraries::Library};
+use anyhow::Result;
+use async_graphql::Object;
+use plist::{Dictionary, Value};
+use sql_builder::prelude::*;
+use sqlx::query_as;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Labels of places kept per library before the cache starts over
+const PLACE_CACHE_SIZE: usize = 200_000;
+
+/// Reverse geocoded location of an asset, as stored by Photos in
+/// ZADDITIONALASSETATTRIBUTES.ZREVERSELOCATIONDATA
+pub struct Place {
+    street: Option<String>,
+    city: Option<String>,
+    state: Option<String>,
+    country: Option<String>,
+    country_code: Option<String>,
+    names: Vec<String>,
+}
+
+#[Object]
+impl Place {
+    /// A short description of the place, such as "Lisbon, Portugal"
+    async fn name(&self) -> Option<String> {
+        self.label()
+    }
+    async fn street(&self) -> &Option<String> {
+        &self.street
+    }
+    async fn city(&self) -> &Option<String> {
+        &self.city
+    }
+    async fn state(&self) -> &Option<String> {
+        &self.state
+    }
+    async fn country(&self) -> &Option<String> {
+        &self.country
+    }
+    /// ISO 3166 code of the country
+    async fn country_code(&self) -> &Option<String> {
+        &self.country_code
+    }
+    /// Names of the areas and points of interest around the place,
+    /// from the most to the least specific
+    async fn names(&self) -> &Vec<String> {
+        &self.names
+    }
+}
+
+impl Place {
+    pub fn decode(blob: &[u8]) -> Result<Self> {
+        let root = unarchive(blob)?;
+        let root = root.as_dictionary();
+
+        let address = root
+            .and_then(|r| r.get("postalAddress"))
+            .and_then(Value::as_dictionary);
+        let field = |key: &str| string(address, key);
+
+        let names = root
+            .and_then(|r| r.get("mapItem"))
+            .and_then(Value::as_dictionary)
+            .and_then(|m| m.get("sortedPlaceInfos"))
+            .and_then(Value::as_array)
+            .map(|infos| {
+                infos
+                    .iter()
+                    .filter_map(|info| string(info.as_dictionary(), "name"))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            street: field("_street"),
+            city: field("_city"),
+            state: field("_state"),
+            country: field("_country"),
+            country_code: field("_ISOCountryCode"),
+            names,
+        })
+    }
+
+    pub fn label(&self) -> Option<String> {
+        let locality = self
+            .city
+            .as_ref()
+            .or(self.state.as_ref())
+            .or_else(|| self.names.first());
+
+        let parts: Vec<&str> = locality
+            .into_iter()
+            .chain(self.country.iter())
+            .map(String::as_str)
+            .collect();
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(", "))
+        }
+    }
+
+    /// All the names the place is known by, for the search index
+    pub fn search_terms(&self) -> String {
+        self.street
+            .iter()
+            .chain(self.city.iter())
+            .chain(self.state.iter())
+            .chain(self.country.iter())
+            .chain(self.names.iter())
+            .map(String::as_str)
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+fn string(dict: Option<&Dictionary>, key: &str) -> Option<String> {
+    dict.and_then(|d| d.get(key))
+        .and_then(Value::as_string)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+/// The number of assets taken at a place
+pub struct PlaceCount {
+    name: String,
+    count: i32,
+}
+
+#[Object]
+impl PlaceCount {
+    async fn name(&self) -> &String {
+        &self.name
+    }
+    async fn count(&self) -> &i32 {
+        &self.count
+    }
+}
+
+/// Remembers the labels of the places assets were taken at, so summarising
+/// the places of an album doesn't decode the location of every asset again
+#[derive(Clone, Default)]
+pub struct PlaceCache(Arc<Mutex<HashMap<i32, Option<String>>>>);
+
+impl PlaceCache {
+    /// Looks up the labels of the places of the assets, returning the ones
+    /// found along with the ids of the assets which aren't cached
+    fn labels(&self, asset_ids: &[i32]) -> (Vec<Option<String>>, Vec<i32>) {
+        let cache = self.lock();
+        let mut labels = vec![];
+        let mut missing = vec![];
+        for id in asset_ids {
+            match cache.get(id) {
+                Some(label) => labels.push(label.clone()),
+                None => missing.push(*id),
+            }
+        }
+        (labels, missing)
+    }
+
+    fn insert(&self, labels: &[(i32, Option<String>)]) {
+        let mut cache = self.lock();
+        if cache.len() + labels.len() > PLACE_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.extend(labels.iter().cloned());
+    }
+
+    /// Forgets all labels, e.g. after Photos.app changed the database
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<i32, Option<String>>> {
+        self.0.lock().expect("Place cache lock is poisoned")
+    }
+}
+
+/// Summarises where the assets matching the sql conditions were taken,
+/// most common places first
+pub async fn place_counts(library: &Library, conditions: &str) -> Result<Vec<PlaceCount>> {
+    let mut asset_ids = visible_assets(library);
+    asset_ids
+        .field("assets.Z_PK")
+        .and_where(format!("({})", conditions));
+
+    let mut located = SqlBuilder::select_from("ZADDITIONALASSETATTRIBUTES");
+    located
+        .field("ZASSET")
+        .and_where_in_query("ZASSET", asset_ids.query()?)
+        .and_where_is_not_null("ZREVERSELOCATIONDATA");
+
+    let ids: Vec<i32> = query_as::<_, (i32,)>(located.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+
+    let (mut labels, missing) = library.places.labels(&ids);
+    if !missing.is_empty() {
+        let mut select = SqlBuilder::select_from("ZADDITIONALASSETATTRIBUTES");
+        select
+            .fields(&["ZASSET", "ZREVERSELOCATIONDATA"])
+            .and_where_in("ZASSET", &missing);
+
+        let decoded: Vec<(i32, Option<String>)> =
+            query_as::<_, (i32, Vec<u8>)>(select.sql()?.as_str())
+                .fetch_all(&library.pool)
+                .await?
+                .into_iter()
+                .map(|(id, blob)| (id, Place::decode(&blob).ok().and_then(|p| p.label())))
+                .collect();
+
+        library.places.insert(&decoded);
+        labels.extend(decoded.into_iter().map(|(_, label)| label));
+    }
+
+    let mut counts: HashMap<String, i32> = HashMap::new();
+    for name in labels.into_iter().flatten() {
+        *counts.entry(name).or_insert(0) += 1;
+    }
+
+    let mut places: Vec<PlaceCount> = counts
+        .into_iter()
+        .map(|(name, count)| PlaceCount { name, count })
+        .collect();
+    places.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(places)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plist::Uid;
+
+    fn dictionary(entries: Vec<(&str, Value)>) -> Value {
+        let mut dict = Dictionary::new();
+        for (key, value) in entries {
+            dict.insert(key.to_string(), value);
+        }
+        Value::Dictionary(dict)
+    }
+
+    fn uid(index: u64) -> Value {
+        Value::Uid(Uid::new(index))
+    }
+
+    fn string(value: &str) -> Value {
+        Value::String(value.to_string())
+    }
+
+    // Archives reverse geocoding data the way Photos.app stores it, with
+    // the address and the names of the places around referenced by uid
+    fn location(address: Vec<(&str, Value)>, names: Vec<&str>) -> Vec<u8> {
+        let first = 5;
+        let infos = (first..first + names.len() as u64).map(uid).collect();
+        let mut objects = vec![
+            string("$null"),
+            dictionary(vec![("postalAddress", uid(2)), ("mapItem", uid(3))]),
+            dictionary(address),
+            dictionary(vec![("sortedPlaceInfos", uid(4))]),
+            dictionary(vec![("NS.objects", Value::Array(infos))]),
+        ];
+        objects.extend(
+            names
+                .into_iter()
+                .map(|name| dictionary(vec![("name", string(name))])),
+        );
+
+        let archive = dictionary(vec![
+            ("$archiver", string("NSKeyedArchiver")),
+            ("$objects", Value::Array(objects)),
+            ("$top", dictionary(vec![("root", uid(1))])),
+        ]);
+        let mut blob = vec![];
+        archive.to_writer_binary(&mut blob).unwrap();
+        blob
+    }
+
+    #[test]
+    fn decodes_addresses_and_names() {
+        let blob = location(
+            vec![
+                ("_street", string("Rua Augusta")),
+                ("_city", string("Lisbon")),
+                ("_state", string("")),
+                ("_country", string("Portugal")),
+                ("_ISOCountryCode", string("PT")),
+            ],
+            vec!["Baixa", "Lisbon"],
+        );
+        let place = Place::decode(&blob).unwrap();
+
+        assert_eq!(place.street.as_deref(), Some("Rua Augusta"));
+        assert_eq!(place.state, None);
+        assert_eq!(place.country_code.as_deref(), Some("PT"));
+        assert_eq!(place.names, vec!["Baixa", "Lisbon"]);
+        assert_eq!(place.label().as_deref(), Some("Lisbon, Portugal"));
+        assert_eq!(
+            place.search_terms(),
+            "Rua Augusta Lisbon Portugal Baixa Lisbon"
+        );
+    }
+
+    #[test]
+    fn labels_places_without_a_city() {
+        let blob = location(vec![], vec!["Atlantic Ocean"]);
+        let place = Place::decode(&blob).unwrap();
+        assert_eq!(place.label().as_deref(), Some("Atlantic Ocean"));
+
+        let blob = location(vec![], vec![]);
+        assert_eq!(Place::decode(&blob).unwrap().label(), None);
+    }
+
+    #[test]
+    fn caches_labels() {
+        let cache = PlaceCache::default();
+        cache.insert(&[(1, Some("Lisbon, Portugal".to_string())), (2, None)]);
+
+        assert_eq!(
+            cache.labels(&[1, 2, 3]),
+            (vec![Some("Lisbon, Portugal".to_string()), None], vec![3])
+        );
+
+        cache.clear();
+        assert_eq!(cache.labels(&[1]), (vec![], vec![1]));
+    }
+}
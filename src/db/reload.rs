@@ -0,0 +1,90 @@
+use super::{libraries::Libraries, resources::ResourceCache};
+use futures::{channel::mpsc::unbounded, StreamExt};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc::channel, Arc};
+use std::time::Duration;
+
+/// Whether a filesystem event is a write to a Photos database or to its
+/// write-ahead log, which is where Photos.app writes first
+fn is_database_change(event: &DebouncedEvent, database: &Path) -> bool {
+    let path = match event {
+        DebouncedEvent::Create(p) | DebouncedEvent::Write(p) | DebouncedEvent::Rename(_, p) => p,
+        _ => return false,
+    };
+
+    let mut wal = database.as_os_str().to_owned();
+    wal.push("-wal");
+
+    path == database || path.as_os_str() == wal
+}
+
+/// Watches the Photos databases of all libraries and detects the model and
+/// reloads the entity cache of a library whenever its database changes,
+/// e.g. after Photos.app migrated it following an OS update. Cached
+/// resources and places of the library are dropped at the same time, as
+/// they may no longer match the database
+pub async fn keep_entities_fresh(libraries: Libraries, resources: Arc<ResourceCache>) {
+    let (tx, mut changes) = unbounded();
+
+    let databases: Vec<(String, PathBuf)> = libraries
+        .iter()
+        .map(|l| (l.name.clone(), PathBuf::from(l.settings.database_url())))
+        .collect();
+
+    // notify delivers events on a blocking channel, so they are forwarded
+    // from a thread of their own
+    std::thread::spawn(move || {
+        let (events_tx, events) = channel();
+
+        let mut w = match watcher(events_tx, Duration::from_secs(2)) {
+            Ok(w) => w,
+            Err(e) => {
+                log::error!("Failed setting up the Photos database watcher: {}", e);
+                return;
+            }
+        };
+
+        for (_, database) in &databases {
+            if let Some(dir) = database.parent() {
+                if let Err(e) = w.watch(dir, RecursiveMode::NonRecursive) {
+                    log::error!("Can't watch {:?} for changes: {}", dir, e);
+                }
+            }
+        }
+
+        for event in events {
+            for (name, database) in &databases {
+                if is_database_change(&event, database) && tx.unbounded_send(name.clone()).is_err()
+                {
+                    return;
+                }
+            }
+        }
+    });
+
+    while let Some(name) = changes.next().await {
+        let library = match libraries.get(Some(name.as_str())) {
+            Ok(l) => l,
+            Err(_) => continue,
+        };
+
+        match library.reload().await {
+            Ok(changed) => {
+                if changed {
+                    log::info!(
+                        "Reloaded the changed model and entities of the '{}' library",
+                        name
+                    );
+                }
+                resources.invalidate(&name);
+                library.places.clear();
+            }
+            Err(e) => log::error!(
+                "Failed reloading the model and entities of the '{}' library: {}",
+                name,
+                e
+            ),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use super::libraries::Library;
+use anyhow::Result;
+use sql_builder::prelude::*;
+use sqlx::{query_as, sqlite::SqlitePool};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Assets whose resources are kept in memory before the cache starts over
+const RESOURCE_CACHE_SIZE: usize = 10_000;
+
+// Photos identifies derivatives by recipe ids, where the upper 16 bits are
+// the kind of the derivative and the lower ones the size
+const KIND_IMAGE: i32 = 1;
+const KIND_VIDEO: i32 = 2;
+const KIND_THUMB: i32 = 4;
+const IMAGE_RENDER: i32 = 201;
+const VIDEO_RENDER: i32 = 0;
+
+/// A derivative of an asset which Photos has generated and keeps locally,
+/// as recorded in ZINTERNALRESOURCE
+#[derive(sqlx::FromRow, Clone)]
+pub struct Resource {
+    recipe: i32,
+    pub width: i32,
+    uti: Option<String>,
+}
+
+impl Resource {
+    fn kind(&self) -> i32 {
+        self.recipe >> 16
+    }
+
+    fn size(&self) -> i32 {
+        self.recipe & 0xFFFF
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.kind() == KIND_VIDEO
+    }
+
+    pub fn is_thumb(&self) -> bool {
+        self.kind() == KIND_THUMB
+    }
+
+    /// Full size renders of edited assets
+    pub fn is_render(&self) -> bool {
+        (self.kind() == KIND_IMAGE && self.size() == IMAGE_RENDER)
+            || (self.kind() == KIND_VIDEO && self.size() == VIDEO_RENDER)
+    }
+
+    fn extension(&self) -> Option<&'static str> {
+        match self.uti.as_deref()? {
+            "public.jpeg" => Some("jpeg"),
+            "public.heic" => Some("heic"),
+            "public.png" => Some("png"),
+            "org.webmproject.webp" => Some("webp"),
+            "com.compuserve.gif" => Some("gif"),
+            "com.apple.quicktime-movie" => Some("mov"),
+            "public.mpeg-4" => Some("mp4"),
+            _ => None,
+        }
+    }
+
+    /// The name Photos gives to the file of this resource, such as
+    /// UUID_1_105_c.jpeg, relative to the directory of the asset
+    pub fn filename(&self, uuid: &str) -> Option<String> {
+        let edition = if self.is_render() { "a" } else { "c" };
+        self.extension().map(|ext| {
+            format!(
+                "{}_{}_{}_{}.{}",
+                uuid,
+                self.kind(),
+                self.size(),
+                edition,
+                ext
+            )
+        })
+    }
+}
+
+async fn resources_by_asset_id(pool: &SqlitePool, asset_id: i32) -> Result<Vec<Resource>> {
+    let fields = [
+        "res.ZRECIPEID as recipe",
+        "IFNULL(res.ZUNORIENTEDWIDTH, 0) as width",
+        "uti.ZIDENTIFIER as uti",
+    ];
+
+    let mut select = SqlBuilder::select_from("ZINTERNALRESOURCE as res");
+    select
+        .fields(&fields)
+        .left()
+        .join("ZUNIFORMTYPEIDENTIFIER as uti")
+        .on("uti.Z_PK = res.ZUNIFORMTYPEIDENTIFIER")
+        .and_where_eq("res.ZASSET", asset_id)
+        .and_where_eq("res.ZLOCALAVAILABILITY", 1)
+        .and_where_gt("res.ZRECIPEID", 0)
+        .order_asc("res.ZUNORIENTEDWIDTH");
+
+    let records = query_as::<_, Resource>(select.sql()?.as_str())
+        .fetch_all(pool)
+        .await?;
+
+    Ok(records)
+}
+
+/// Remembers which resources exist for the assets requested recently, so
+/// serving their files doesn't hit the database every time
+#[derive(Default)]
+pub struct ResourceCache {
+    resources: Mutex<HashMap<(String, i32), Vec<Resource>>>,
+}
+
+impl ResourceCache {
+    pub async fn resources(&self, library: &Library, asset_id: i32) -> Result<Vec<Resource>> {
+        let key = (library.name.clone(), asset_id);
+        if let Some(resources) = self.lock().get(&key) {
+            return Ok(resources.clone());
+        }
+
+        let resources = resources_by_asset_id(&library.pool, asset_id).await?;
+
+        let mut cache = self.lock();
+        if cache.len() >= RESOURCE_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(key, resources.clone());
+
+        Ok(resources)
+    }
+
+    /// Forgets the resources of all assets of a library
+    pub fn invalidate(&self, library: &str) {
+        self.lock().retain(|(name, _), _| name != library);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, i32), Vec<Resource>>> {
+        self.resources
+            .lock()
+            .expect("Resource cache lock is poisoned")
+    }
+}
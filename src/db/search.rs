@@ -0,0 +1,552 @@
+use super::{
+    albums::{album_kinds, Album, AllowedAlbumIds},
+    assets::{asset_scope, visible_assets, Asset},
+    entities::entity_id,
+    errors::{graphql_error, XpozError},
+    libraries::{library, Libraries, Library},
+    loaders::loaders,
+    pagination::{ConnectionFields, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+    places::Place,
+    tokens::Token,
+};
+use anyhow::Result;
+use async_graphql::{
+    connection::{Connection, Edge, EmptyFields},
+    Context, Enum, Object, Result as AGResult,
+};
+use sql_builder::prelude::*;
+use sqlx::{query, query_as, sqlite::SqlitePool};
+use std::{cmp::Ordering, collections::HashSet, time::Duration};
+
+// Number of modified assets picked up from the Photos database at a time
+const INDEX_BATCH_SIZE: i32 = 500;
+
+pub type SearchConnection = Connection<usize, SearchHit, ConnectionFields, EmptyFields>;
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum SearchKind {
+    Album,
+    Asset,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct SearchHit {
+    library: String,
+    kind: String,
+    uuid: String,
+    title: String,
+    snippet: String,
+    asset_id: Option<i32>,
+    rank: f64,
+}
+
+#[Object]
+impl SearchHit {
+    async fn kind(&self) -> SearchKind {
+        match self.kind.as_str() {
+            "album" => SearchKind::Album,
+            _ => SearchKind::Asset,
+        }
+    }
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
+    }
+    /// The title of the match, with the matching terms wrapped in <mark>
+    async fn title(&self) -> &String {
+        &self.title
+    }
+    /// The best matching fragment of the indexed text, with the matching
+    /// terms wrapped in <mark>
+    async fn snippet(&self) -> &String {
+        &self.snippet
+    }
+    async fn album(&self, ctx: &Context<'_>) -> AGResult<Option<Album>> {
+        if self.kind != "album" {
+            return Ok(None);
+        }
+        Ok(loaders(ctx, &self.library)?
+            .albums
+            .load_one(self.uuid.clone())
+            .await
+            .map_err(graphql_error)?)
+    }
+    async fn asset(&self, ctx: &Context<'_>) -> AGResult<Option<Asset>> {
+        if self.kind != "asset" {
+            return Ok(None);
+        }
+        let asset_id = match self.asset_id {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        Ok(loaders(ctx, &self.library)?
+            .assets
+            .load_one(asset_id)
+            .await
+            .map_err(graphql_error)?)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct IndexedAlbum {
+    uuid: String,
+    title: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct IndexedAsset {
+    id: i32,
+    uuid: String,
+    modified_at: f64,
+    visible: bool,
+    title: Option<String>,
+    caption: Option<String>,
+    keywords: Option<String>,
+    people: Option<String>,
+    reverse_location: Option<Vec<u8>>,
+}
+
+impl IndexedAsset {
+    fn places(&self) -> Option<String> {
+        Place::decode(self.reverse_location.as_ref()?)
+            .ok()
+            .map(|place| place.search_terms())
+    }
+
+    fn is_searchable(&self) -> bool {
+        self.visible
+            && (self.title.is_some()
+                || self.caption.is_some()
+                || self.keywords.is_some()
+                || self.people.is_some()
+                || self.reverse_location.is_some())
+    }
+}
+
+/// Keeps refreshing the search index of every library for as long as the
+/// server is running
+pub async fn keep_index_fresh(app: SqlitePool, libraries: Libraries, interval: u64) {
+    loop {
+        for library in libraries.iter() {
+            if let Err(e) = refresh_index(&app, library).await {
+                log::error!(
+                    "Failed refreshing the search index of library '{}': {}",
+                    library.name,
+                    e
+                );
+            }
+        }
+        actix_web::rt::time::delay_for(Duration::from_secs(interval)).await;
+    }
+}
+
+/// Copies the searchable text of albums and assets from the Photos
+/// database to the search index. Assets are only reindexed when they
+/// were modified since the last refresh
+pub async fn refresh_index(app: &SqlitePool, library: &Library) -> Result<()> {
+    index_albums(app, library).await?;
+    index_assets(app, library).await?;
+    Ok(())
+}
+
+async fn index_albums(app: &SqlitePool, library: &Library) -> Result<()> {
+    let mut select = SqlBuilder::select_from("ZGENERICALBUM");
+    select
+        .fields(&["ZUUID as uuid", "ZTITLE as title"])
+        .and_where(album_kinds(&library.entities()))
+        .and_where_is_not_null("ZTITLE")
+        .and_where_lt("ZTRASHEDSTATE", 1);
+
+    let albums = query_as::<_, IndexedAlbum>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    let mut tx = app.begin().await?;
+
+    query("DELETE FROM search_index WHERE library = ? AND kind = 'album'")
+        .bind(&library.name)
+        .execute(&mut tx)
+        .await?;
+
+    for album in albums {
+        query("INSERT INTO search_index (library, kind, uuid, title) VALUES (?, 'album', ?, ?)")
+            .bind(&library.name)
+            .bind(&album.uuid)
+            .bind(&album.title)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn index_assets(app: &SqlitePool, library: &Library) -> Result<()> {
+    let (mut modified_at, mut id) = (last_indexed(app, library, "asset").await?, i32::MAX);
+
+    loop {
+        let assets = modified_assets(library, modified_at, id).await?;
+        if assets.is_empty() {
+            break;
+        }
+
+        let mut tx = app.begin().await?;
+
+        for asset in &assets {
+            query("DELETE FROM search_index WHERE library = ? AND kind = 'asset' AND uuid = ?")
+                .bind(&library.name)
+                .bind(&asset.uuid)
+                .execute(&mut tx)
+                .await?;
+
+            if !asset.is_searchable() {
+                continue;
+            }
+
+            query(
+                "INSERT INTO search_index \
+                (library, kind, uuid, title, caption, keywords, people, places) \
+                VALUES (?, 'asset', ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&library.name)
+            .bind(&asset.uuid)
+            .bind(asset.title.as_deref().unwrap_or(""))
+            .bind(asset.caption.as_deref().unwrap_or(""))
+            .bind(asset.keywords.as_deref().unwrap_or(""))
+            .bind(asset.people.as_deref().unwrap_or(""))
+            .bind(asset.places().unwrap_or_default())
+            .execute(&mut tx)
+            .await?;
+        }
+
+        let last = assets.last().expect("Batch of modified assets is empty");
+        modified_at = last.modified_at;
+        id = last.id;
+
+        query(
+            "INSERT OR REPLACE INTO search_index_state (library, kind, modified_at) \
+            VALUES (?, 'asset', ?)",
+        )
+        .bind(&library.name)
+        .bind(modified_at)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        if (assets.len() as i32) < INDEX_BATCH_SIZE {
+            break;
+        }
+    }
+
+    remove_stale_assets(app, library).await
+}
+
+/// Removes the assets which were deleted, trashed or hidden since they
+/// were indexed. Assets deleted from the Photos database don't show up as
+/// modified, so they're found by comparing the index with the library
+async fn remove_stale_assets(app: &SqlitePool, library: &Library) -> Result<()> {
+    let mut select = visible_assets(library);
+    select.field("assets.ZUUID");
+    let visible: HashSet<String> = query_as::<_, (String,)>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?
+        .into_iter()
+        .map(|(uuid,)| uuid)
+        .collect();
+
+    let indexed: Vec<String> = query_as::<_, (String,)>(
+        "SELECT uuid FROM search_index WHERE library = ? AND kind = 'asset'",
+    )
+    .bind(&library.name)
+    .fetch_all(app)
+    .await?
+    .into_iter()
+    .map(|(uuid,)| uuid)
+    .collect();
+
+    let stale = stale_uuids(indexed, &visible);
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = app.begin().await?;
+    for uuid in &stale {
+        query("DELETE FROM search_index WHERE library = ? AND kind = 'asset' AND uuid = ?")
+            .bind(&library.name)
+            .bind(uuid)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    log::debug!(
+        "Removed {} stale assets of library '{}' from the search index",
+        stale.len(),
+        library.name
+    );
+
+    Ok(())
+}
+
+/// The indexed uuids which aren't among the visible assets anymore
+fn stale_uuids(indexed: Vec<String>, visible: &HashSet<String>) -> Vec<String> {
+    indexed
+        .into_iter()
+        .filter(|uuid| !visible.contains(uuid))
+        .collect()
+}
+
+async fn last_indexed(pool: &SqlitePool, library: &Library, kind: &str) -> Result<f64> {
+    let result = query_as::<_, (f64,)>(
+        "SELECT modified_at FROM search_index_state WHERE library = ? AND kind = ?",
+    )
+    .bind(&library.name)
+    .bind(kind)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result.map_or(0.0, |(modified_at,)| modified_at))
+}
+
+/// Assets modified after the given position, in the order of modification
+async fn modified_assets(
+    library: &Library,
+    modified_at: f64,
+    id: i32,
+) -> Result<Vec<IndexedAsset>> {
+    let attrs = entity_id(&library.entities(), "AdditionalAssetAttributes")?;
+    let keyword = entity_id(&library.entities(), "Keyword")?;
+
+    let keywords = format!(
+        "(SELECT group_concat(kw.ZTITLE, ' ') FROM Z_{a}KEYWORDS kw_joins \
+        JOIN ZKEYWORD kw ON kw.Z_PK = kw_joins.Z_{k}KEYWORDS \
+        WHERE kw_joins.Z_{a}ASSETATTRIBUTES = attrs.Z_PK) as keywords",
+        a = attrs,
+        k = keyword
+    );
+    let people = format!(
+        "(SELECT group_concat(people.ZFULLNAME, ' ') FROM ZDETECTEDFACE faces \
+        JOIN ZPERSON people ON people.Z_PK = faces.{person} \
+        WHERE faces.{asset} = assets.Z_PK) as people",
+        person = library.model().face_person,
+        asset = library.model().face_asset
+    );
+
+    let fields = [
+        "assets.Z_PK as id".to_string(),
+        "assets.ZUUID as uuid".to_string(),
+        "assets.ZMODIFICATIONDATE as modified_at".to_string(),
+        "(assets.ZTRASHEDSTATE < 1 AND assets.ZHIDDEN < 1) as visible".to_string(),
+        "attrs.ZTITLE as title".to_string(),
+        "descr.ZLONGDESCRIPTION as caption".to_string(),
+        "attrs.ZREVERSELOCATIONDATA as reverse_location".to_string(),
+        keywords,
+        people,
+    ];
+
+    let mut select = SqlBuilder::select_from(format!("{} as assets", library.model().assets));
+    select
+        .fields(&fields)
+        .left()
+        .join("ZADDITIONALASSETATTRIBUTES as attrs")
+        .on("attrs.ZASSET = assets.Z_PK")
+        .left()
+        .join("ZASSETDESCRIPTION as descr")
+        .on("descr.ZASSETATTRIBUTES = attrs.Z_PK")
+        .and_where_is_not_null("assets.ZMODIFICATIONDATE")
+        .and_where(
+            "(assets.ZMODIFICATIONDATE > ? OR (assets.ZMODIFICATIONDATE = ? AND assets.Z_PK > ?))"
+                .bind(&modified_at)
+                .bind(&modified_at)
+                .bind(&id),
+        )
+        .order_asc("assets.ZMODIFICATIONDATE")
+        .order_asc("assets.Z_PK")
+        .limit(INDEX_BATCH_SIZE);
+
+    let records = query_as::<_, IndexedAsset>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
+
+/// Turns user input into an fts5 query, matching all of the words with
+/// the last one treated as a prefix
+fn match_expression(input: &str) -> Option<String> {
+    let words: Vec<String> = input
+        .split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect();
+
+    if words.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}*", words.join(" ")))
+}
+
+/// Sql conditions on the search index limiting the results to what the
+/// whitelist gives access to
+fn search_scope(library: &Library, whitelist: &AllowedAlbumIds) -> Result<String> {
+    match whitelist {
+        None => Ok("1".to_string()),
+        Some(wl) => Ok(format!(
+            "((kind = 'album' AND uuid IN (SELECT ZUUID FROM ZGENERICALBUM WHERE {albums})) \
+            OR (kind = 'asset' AND uuid IN (SELECT assets.ZUUID FROM {table} as assets WHERE {assets})))",
+            albums = wl.album_conditions(),
+            table = library.model().assets,
+            assets = asset_scope(library, whitelist)?
+        )),
+    }
+}
+
+/// Runs a full text search against the search index of every library the
+/// token can browse. Each library is searched on its own Photos database,
+/// which has the app database attached as `xpoz`, so results can be scoped
+/// with the usual conditions, and the best matches of all are merged
+pub async fn search(
+    libraries: &Libraries,
+    token: &Token,
+    input: &str,
+    after: Option<usize>,
+    first: Option<usize>,
+) -> Result<SearchConnection> {
+    let size = first.unwrap_or(DEFAULT_PAGE_SIZE);
+    if size > MAX_PAGE_SIZE {
+        return Err(XpozError::Validation(format!(
+            "Can't request more than {} records at a time",
+            MAX_PAGE_SIZE
+        ))
+        .into());
+    }
+    let offset = after.map_or(0, |a| a + 1);
+
+    let expression = match match_expression(input) {
+        Some(e) => e,
+        None => {
+            return Ok(Connection::with_additional_fields(
+                false,
+                false,
+                ConnectionFields { total_count: 0 },
+            ))
+        }
+    };
+
+    let mut total_count = 0;
+    let mut records = vec![];
+    for library in libraries.iter() {
+        let (count, hits) = search_library(
+            library,
+            &token.whitelist_for(library),
+            &expression,
+            offset + size,
+        )
+        .await?;
+        total_count += count;
+        records.extend(hits);
+    }
+
+    // fts5 ranks are comparable across libraries as they share the index
+    records.sort_by(|a, b| a.rank.partial_cmp(&b.rank).unwrap_or(Ordering::Equal));
+    let records: Vec<SearchHit> = records.into_iter().skip(offset).take(size).collect();
+
+    let has_next_page = ((offset + records.len()) as i64) < total_count;
+    let mut connection = Connection::with_additional_fields(
+        offset > 0,
+        has_next_page,
+        ConnectionFields { total_count },
+    );
+    connection.append(
+        records
+            .into_iter()
+            .enumerate()
+            .map(|(i, record)| Edge::new(offset + i, record)),
+    );
+
+    Ok(connection)
+}
+
+/// Counts the matches of the fts5 expression in a library and returns the
+/// best `limit` of them
+async fn search_library(
+    library: &Library,
+    whitelist: &AllowedAlbumIds,
+    expression: &str,
+    limit: usize,
+) -> Result<(i64, Vec<SearchHit>)> {
+    let mut select = SqlBuilder::select_from("xpoz.search_index");
+    select
+        .and_where("search_index MATCH ?".bind(&expression))
+        .and_where("library = ?".bind(&library.name))
+        .and_where(search_scope(library, whitelist)?);
+
+    let mut count = select.clone();
+    count.field("COUNT(*)");
+    let (total_count,) = query_as::<_, (i64,)>(count.sql()?.as_str())
+        .fetch_one(&library.pool)
+        .await?;
+
+    select
+        .fields(&[
+            "library",
+            "kind",
+            "uuid",
+            "highlight(search_index, 3, '<mark>', '</mark>') as title",
+            "snippet(search_index, -1, '<mark>', '</mark>', '…', 12) as snippet",
+        ])
+        .field(format!(
+            "CASE kind WHEN 'asset' THEN \
+            (SELECT Z_PK FROM {} WHERE ZUUID = search_index.uuid) END as asset_id",
+            library.model().assets
+        ))
+        .field("rank")
+        .order_asc("rank")
+        .limit(limit);
+
+    let records = query_as::<_, SearchHit>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok((total_count, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_all_words_and_the_last_as_a_prefix() {
+        assert_eq!(
+            match_expression("  lisbon  tra ").as_deref(),
+            Some("\"lisbon\" \"tra\"*")
+        );
+    }
+
+    #[test]
+    fn escapes_quotes() {
+        assert_eq!(
+            match_expression("say \"cheese").as_deref(),
+            Some("\"say\" \"\"\"cheese\"*")
+        );
+    }
+
+    #[test]
+    fn finds_assets_which_are_not_visible_anymore() {
+        let visible: HashSet<String> = vec!["kept".to_string(), "unindexed".to_string()]
+            .into_iter()
+            .collect();
+        let indexed = vec![
+            "kept".to_string(),
+            "deleted".to_string(),
+            "hidden".to_string(),
+        ];
+        assert_eq!(stale_uuids(indexed, &visible), vec!["deleted", "hidden"]);
+    }
+
+    #[test]
+    fn matches_nothing_without_words() {
+        assert_eq!(match_expression(" \t"), None);
+    }
+}
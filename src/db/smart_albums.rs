@@ -0,0 +1,345 @@
+use super::{archive::unarchive, entities::Entity, keywords::keyword_assets, model::Model};
+use anyhow::{anyhow, Result};
+use plist::Value;
+use sql_builder::quote;
+
+/// ZKIND of the smart albums created by the user in Photos.app
+pub const SMART_ALBUM_KIND: i32 = 1507;
+
+// Search keys of the supported criteria in an archived smart album query
+const KEY_DATE_CREATED: i64 = 2;
+const KEY_KEYWORD: i64 = 7;
+const KEY_PERSON: i64 = 13;
+const KEY_FAVORITE: i64 = 16;
+const KEY_MEDIA_TYPE: i64 = 19;
+
+const SECONDS_IN_A_DAY: f64 = 86400.0;
+
+enum Comparator {
+    Is,
+    IsNot,
+    Contains,
+    DoesNotContain,
+    Before,
+    After,
+    InRange,
+}
+
+impl Comparator {
+    fn from_code(code: i64) -> Result<Self> {
+        match code {
+            0 => Ok(Self::Is),
+            1 => Ok(Self::IsNot),
+            2 => Ok(Self::Contains),
+            3 => Ok(Self::DoesNotContain),
+            4 => Ok(Self::Before),
+            5 => Ok(Self::After),
+            6 => Ok(Self::InRange),
+            _ => Err(anyhow!("Unsupported smart album comparator {}", code)),
+        }
+    }
+
+    fn is_negated(&self) -> bool {
+        matches!(self, Self::IsNot | Self::DoesNotContain)
+    }
+}
+
+enum Criterion {
+    // dates are seconds since the Apple epoch, the same as ZASSET.ZDATECREATED
+    DateCreated(Comparator, f64, Option<f64>),
+    Keyword(Comparator, String),
+    Person(Comparator, String),
+    Favorite(bool),
+    MediaType(Comparator, i64),
+}
+
+/// A smart album query as stored in ZGENERICALBUM.ZCUSTOMQUERYPARAMETERS
+pub struct SmartQuery {
+    match_all: bool,
+    criteria: Vec<Criterion>,
+}
+
+impl SmartQuery {
+    pub fn parse(blob: &[u8]) -> Result<Self> {
+        let root = unarchive(blob)?;
+        let root = root
+            .as_dictionary()
+            .ok_or_else(|| anyhow!("Smart album query is not a dictionary"))?;
+
+        let match_all = root
+            .get("conjunction")
+            .and_then(Value::as_signed_integer)
+            .unwrap_or(0)
+            == 0;
+
+        let criteria = root
+            .get("queries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("Smart album query has no criteria"))?
+            .iter()
+            .map(criterion)
+            .collect::<Result<Vec<Criterion>>>()?;
+
+        Ok(Self {
+            match_all,
+            criteria,
+        })
+    }
+
+    /// Translates the query into sql conditions on the `assets` table of a
+    /// library with the given entities and database model
+    pub fn to_sql(&self, entities: &[Entity], model: &Model) -> Result<String> {
+        if self.criteria.is_empty() {
+            return Ok("1".to_string());
+        }
+
+        let conditions = self
+            .criteria
+            .iter()
+            .map(|c| condition(c, entities, model).map(|sql| format!("({})", sql)))
+            .collect::<Result<Vec<String>>>()?;
+
+        let conjunction = if self.match_all { " AND " } else { " OR " };
+        Ok(format!("({})", conditions.join(conjunction)))
+    }
+}
+
+fn criterion(value: &Value) -> Result<Criterion> {
+    let query = value
+        .as_dictionary()
+        .ok_or_else(|| anyhow!("Smart album criterion is not a dictionary"))?;
+    let int = |key: &str| query.get(key).and_then(Value::as_signed_integer);
+    let real = |key: &str| {
+        query.get(key).and_then(|v| {
+            v.as_real()
+                .or_else(|| v.as_signed_integer().map(|i| i as f64))
+        })
+    };
+    let string = |key: &str| {
+        query
+            .get(key)
+            .and_then(Value::as_string)
+            .map(|s| s.to_string())
+            .ok_or_else(|| anyhow!("Smart album criterion is missing a value"))
+    };
+
+    let key = int("key").ok_or_else(|| anyhow!("Smart album criterion has no key"))?;
+    let comparator = Comparator::from_code(int("comparator").unwrap_or(0))?;
+
+    match key {
+        KEY_DATE_CREATED => {
+            let first = real("first").ok_or_else(|| anyhow!("Date criterion has no date"))?;
+            Ok(Criterion::DateCreated(comparator, first, real("second")))
+        }
+        KEY_KEYWORD => Ok(Criterion::Keyword(comparator, string("first")?)),
+        KEY_PERSON => Ok(Criterion::Person(comparator, string("first")?)),
+        KEY_FAVORITE => {
+            let favorite = query
+                .get("first")
+                .and_then(|v| {
+                    v.as_boolean()
+                        .or_else(|| v.as_signed_integer().map(|i| i != 0))
+                })
+                .unwrap_or(true);
+            Ok(Criterion::Favorite(favorite != comparator.is_negated()))
+        }
+        KEY_MEDIA_TYPE => {
+            let kind = int("first").ok_or_else(|| anyhow!("Media type criterion has no type"))?;
+            Ok(Criterion::MediaType(comparator, kind))
+        }
+        _ => Err(anyhow!("Unsupported smart album criterion {}", key)),
+    }
+}
+
+fn condition(criterion: &Criterion, entities: &[Entity], model: &Model) -> Result<String> {
+    match criterion {
+        Criterion::DateCreated(comparator, first, second) => {
+            let date = "assets.ZDATECREATED";
+            match comparator {
+                Comparator::Before => Ok(format!("{} < {}", date, first)),
+                Comparator::After => Ok(format!("{} > {}", date, first)),
+                Comparator::InRange => Ok(format!(
+                    "{} BETWEEN {} AND {}",
+                    date,
+                    first,
+                    second.ok_or_else(|| anyhow!("Date range criterion has no end date"))?
+                )),
+                Comparator::IsNot => Ok(format!(
+                    "{} NOT BETWEEN {} AND {}",
+                    date,
+                    first,
+                    first + SECONDS_IN_A_DAY
+                )),
+                _ => Ok(format!(
+                    "{} BETWEEN {} AND {}",
+                    date,
+                    first,
+                    first + SECONDS_IN_A_DAY
+                )),
+            }
+        }
+        Criterion::Keyword(comparator, title) => {
+            let conditions = format!("kw.ZTITLE = {}", quote(title));
+            let subquery = keyword_assets(entities, &conditions)?;
+            Ok(membership(comparator, subquery))
+        }
+        Criterion::Person(comparator, name) => {
+            let conditions = format!(
+                "people.ZFULLNAME = {name} OR people.ZDISPLAYNAME = {name}",
+                name = quote(name)
+            );
+            Ok(membership(comparator, model.person_assets(&conditions)))
+        }
+        Criterion::Favorite(favorite) => Ok(format!("assets.ZFAVORITE = {}", *favorite as i32)),
+        Criterion::MediaType(comparator, kind) => {
+            let operator = if comparator.is_negated() { "<>" } else { "=" };
+            Ok(format!("assets.ZKIND {} {}", operator, kind))
+        }
+    }
+}
+
+fn membership(comparator: &Comparator, subquery: String) -> String {
+    let operator = if comparator.is_negated() {
+        "NOT IN"
+    } else {
+        "IN"
+    };
+    format!("assets.Z_PK {} ({})", operator, subquery)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plist::{Dictionary, Uid};
+
+    const MODEL: Model = Model {
+        version: 15000,
+        assets: "ZASSET",
+        face_asset: "ZASSET",
+        face_person: "ZPERSON",
+        custom_key_asset: "ZCUSTOMKEYASSET",
+    };
+
+    fn dictionary(entries: Vec<(&str, Value)>) -> Value {
+        let mut dict = Dictionary::new();
+        for (key, value) in entries {
+            dict.insert(key.to_string(), value);
+        }
+        Value::Dictionary(dict)
+    }
+
+    fn uid(index: u64) -> Value {
+        Value::Uid(Uid::new(index))
+    }
+
+    // Archives a query the way Photos.app stores it, with the criteria
+    // referenced from an NSArray
+    fn archive(conjunction: i64, criteria: Vec<Value>) -> Vec<u8> {
+        let first = 3;
+        let references = (first..first + criteria.len() as u64).map(uid).collect();
+        let mut objects = vec![
+            Value::String("$null".to_string()),
+            dictionary(vec![
+                ("conjunction", Value::Integer(conjunction.into())),
+                ("queries", uid(2)),
+            ]),
+            dictionary(vec![("NS.objects", Value::Array(references))]),
+        ];
+        objects.extend(criteria);
+
+        let archive = dictionary(vec![
+            ("$archiver", Value::String("NSKeyedArchiver".to_string())),
+            ("$objects", Value::Array(objects)),
+            ("$top", dictionary(vec![("root", uid(1))])),
+        ]);
+        let mut blob = vec![];
+        archive.to_writer_binary(&mut blob).unwrap();
+        blob
+    }
+
+    fn criterion(key: i64, comparator: i64, first: Value) -> Value {
+        dictionary(vec![
+            ("key", Value::Integer(key.into())),
+            ("comparator", Value::Integer(comparator.into())),
+            ("first", first),
+        ])
+    }
+
+    fn to_sql(blob: &[u8]) -> String {
+        SmartQuery::parse(blob)
+            .unwrap()
+            .to_sql(&[], &MODEL)
+            .unwrap()
+    }
+
+    #[test]
+    fn joins_criteria_matching_all() {
+        let blob = archive(
+            0,
+            vec![
+                criterion(KEY_FAVORITE, 0, Value::Boolean(true)),
+                criterion(KEY_MEDIA_TYPE, 0, Value::Integer(1.into())),
+            ],
+        );
+        assert_eq!(
+            to_sql(&blob),
+            "((assets.ZFAVORITE = 1) AND (assets.ZKIND = 1))"
+        );
+    }
+
+    #[test]
+    fn groups_criteria_matching_any() {
+        let blob = archive(
+            1,
+            vec![
+                criterion(KEY_FAVORITE, 1, Value::Boolean(true)),
+                criterion(KEY_MEDIA_TYPE, 1, Value::Integer(1.into())),
+            ],
+        );
+        assert_eq!(
+            to_sql(&blob),
+            "((assets.ZFAVORITE = 0) OR (assets.ZKIND <> 1))"
+        );
+    }
+
+    #[test]
+    fn translates_date_ranges() {
+        let mut range = criterion(KEY_DATE_CREATED, 6, Value::Real(100.0));
+        if let Value::Dictionary(dict) = &mut range {
+            dict.insert("second".to_string(), Value::Real(200.0));
+        }
+        let blob = archive(0, vec![range]);
+        assert_eq!(to_sql(&blob), "((assets.ZDATECREATED BETWEEN 100 AND 200))");
+    }
+
+    #[test]
+    fn quotes_names_of_people() {
+        let name = Value::String("O'Brien".to_string());
+        let blob = archive(0, vec![criterion(KEY_PERSON, 0, name)]);
+        let sql = to_sql(&blob);
+        assert!(sql.contains("people.ZFULLNAME = 'O''Brien'"));
+        assert!(sql.contains("SELECT faces.ZASSET FROM"));
+    }
+
+    #[test]
+    fn matches_everything_without_criteria() {
+        assert_eq!(to_sql(&archive(0, vec![])), "1");
+    }
+
+    #[test]
+    fn fails_on_unsupported_criteria() {
+        let blob = archive(0, vec![criterion(99, 0, Value::Boolean(true))]);
+        assert!(SmartQuery::parse(&blob).is_err());
+        assert!(SmartQuery::parse(b"not a plist").is_err());
+    }
+
+    #[test]
+    fn fails_on_keywords_without_keyword_entities() {
+        let keyword = Value::String("Travel".to_string());
+        let blob = archive(0, vec![criterion(KEY_KEYWORD, 0, keyword)]);
+        assert!(SmartQuery::parse(&blob)
+            .unwrap()
+            .to_sql(&[], &MODEL)
+            .is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use super::{
+    assets::{assets_matching, visible_assets, Asset, AssetFilter},
+    dates::{local_capture_time, Timestamp},
+    errors::graphql_error,
+    libraries::{library, Library},
+    pagination::{Page, PagedConnection},
+    permissions::Permission,
+    tokens::Token,
+};
+use anyhow::Result;
+use async_graphql::{connection::query, Context, Enum, Object, Result as AGResult};
+use sql_builder::prelude::*;
+use sqlx::query_as;
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum Granularity {
+    Day,
+    Month,
+    Year,
+}
+
+impl Granularity {
+    /// Sql expression grouping the assets in buckets of this granularity
+    fn bucket_key(&self) -> String {
+        let format = match self {
+            Self::Day => "%Y-%m-%d",
+            Self::Month => "%Y-%m",
+            Self::Year => "%Y",
+        };
+        format!(
+            "strftime('{}',{},'unixepoch')",
+            format,
+            local_capture_time()
+        )
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct BucketRow {
+    key: String,
+    count: i32,
+    starts_at: f64,
+    ends_at: f64,
+}
+
+/// All assets captured within the same day, month or year
+pub struct Bucket {
+    library: String,
+    key: String,
+    count: i32,
+    starts_at: f64,
+    ends_at: f64,
+    conditions: String,
+}
+
+#[Object]
+impl Bucket {
+    /// The date of the bucket formatted as YYYY-MM-DD, YYYY-MM or YYYY
+    async fn key(&self) -> &String {
+        &self.key
+    }
+    async fn count(&self) -> &i32 {
+        &self.count
+    }
+    async fn starts_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.starts_at)
+    }
+    async fn ends_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.ends_at)
+    }
+
+    /// The moments Photos has grouped the assets of this bucket in
+    async fn moments(&self, ctx: &Context<'_>) -> AGResult<Vec<Moment>> {
+        Ok(moments(library(ctx, &self.library)?, &self.conditions)
+            .await
+            .map_err(graphql_error)?)
+    }
+
+    async fn assets(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        filter: Option<AssetFilter>,
+    ) -> AGResult<PagedConnection<Asset>> {
+        let library = library(ctx, &self.library)?;
+        let filter = filter.unwrap_or_default();
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let page = Page::new(after, before, first, last);
+                assets_matching(library, &self.conditions, &filter, &page)
+                    .await
+                    .map_err(graphql_error)
+            },
+        )
+        .await
+    }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct Moment {
+    uuid: String,
+    library: String,
+    title: Option<String>,
+    subtitle: Option<String>,
+    latitude: f32,
+    longitude: f32,
+    starts_at: f64,
+    ends_at: f64,
+}
+
+#[Object]
+impl Moment {
+    async fn id(&self, ctx: &Context<'_>) -> AGResult<String> {
+        Ok(library(ctx, &self.library)?.qualify(&self.uuid))
+    }
+    /// Name of the library the moment belongs to
+    async fn library(&self) -> &String {
+        &self.library
+    }
+    async fn title(&self) -> &Option<String> {
+        &self.title
+    }
+    async fn subtitle(&self) -> &Option<String> {
+        &self.subtitle
+    }
+    async fn latitude(&self) -> &f32 {
+        &self.latitude
+    }
+    async fn longitude(&self) -> &f32 {
+        &self.longitude
+    }
+    async fn starts_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.starts_at)
+    }
+    async fn ends_at(&self) -> Option<Timestamp> {
+        Timestamp::from_apple_utc(self.ends_at)
+    }
+}
+
+/// Sql conditions on the `assets` table limiting what a token can see on
+/// the timeline. Tokens allowed to browse everything see the whole library,
+/// other tokens only see the date ranges they've been given access to
+pub fn timeline_scope(token: &Token) -> Option<String> {
+    if token.can(Permission::BrowseAll) {
+        return Some("1".to_string());
+    }
+    token.whitelist().and_then(|wl| wl.date_range_conditions())
+}
+
+pub async fn timeline(
+    library: &Library,
+    scope: &str,
+    granularity: Granularity,
+) -> Result<Vec<Bucket>> {
+    let key = granularity.bucket_key();
+    let fields = [
+        format!("{} as key", key),
+        "COUNT(*) as count".to_string(),
+        "MIN(assets.ZDATECREATED) as starts_at".to_string(),
+        "MAX(assets.ZDATECREATED) as ends_at".to_string(),
+    ];
+
+    let mut select = visible_assets(library);
+    select
+        .fields(&fields)
+        .and_where(format!("({})", scope))
+        .and_where_is_not_null("assets.ZDATECREATED")
+        .group_by(&key)
+        .order_desc(&key);
+
+    let rows = query_as::<_, BucketRow>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    let buckets = rows
+        .into_iter()
+        .map(|row| Bucket {
+            library: library.name.clone(),
+            conditions: format!("({}) AND {}", scope, format!("{} = ?", key).bind(&row.key)),
+            key: row.key,
+            count: row.count,
+            starts_at: row.starts_at,
+            ends_at: row.ends_at,
+        })
+        .collect();
+
+    Ok(buckets)
+}
+
+async fn moments(library: &Library, conditions: &str) -> Result<Vec<Moment>> {
+    let fields = [
+        "ZUUID as uuid",
+        "ZTITLE as title",
+        "ZSUBTITLE as subtitle",
+        "ZAPPROXIMATELATITUDE as latitude",
+        "ZAPPROXIMATELONGITUDE as longitude",
+        "ZSTARTDATE as starts_at",
+        "ZENDDATE as ends_at",
+    ];
+
+    let mut moment_ids = visible_assets(library);
+    moment_ids
+        .field("assets.ZMOMENT")
+        .and_where(format!("({})", conditions));
+
+    let mut select = SqlBuilder::select_from("ZMOMENT");
+    select
+        .fields(&fields)
+        .field(library.name_field())
+        .and_where_in_query("Z_PK", moment_ids.query()?)
+        .order_asc("ZSTARTDATE");
+
+    let records = query_as::<_, Moment>(select.sql()?.as_str())
+        .fetch_all(&library.pool)
+        .await?;
+
+    Ok(records)
+}
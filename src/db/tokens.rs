@@ -1,27 +1,109 @@
-use super::albums::{my_albums, Album, AllowedAlbumIds};
-use super::Databases;
-use super::entities::Entity;
-use crate::db::bool_to_insert_string;
+use super::albums::{Album, AllowedAlbumIds};
+use super::errors::{graphql_error, XpozError};
+use super::libraries::{Libraries, Library};
+use super::loaders::loaders;
+use super::permissions::{Permission, Role};
+use super::whitelist::{DateRange, Whitelist};
 use anyhow::Result;
-use async_graphql::{Context, InputObject, Object, Result as AGResult};
+use async_graphql::{Context, ErrorExtensions, InputObject, Object, Result as AGResult};
 use nanoid::nanoid;
-use sql_builder::prelude::*;
 use sqlx::{query, query_as, sqlite::SqlitePool, Done};
 
+#[derive(InputObject)]
+pub struct DateRangeInput {
+    /// First day of the range, formatted as YYYY-MM-DD
+    from: String,
+    /// Last day of the range, formatted as YYYY-MM-DD
+    to: String,
+}
+
 #[derive(InputObject)]
 pub struct TokenInput {
     name: String,
     session_bound: bool,
-    admin: bool,
+    role: Role,
     album_ids: Option<Vec<String>>,
+    folder_ids: Option<Vec<String>>,
+    person_ids: Option<Vec<String>>,
+    keyword_ids: Option<Vec<String>>,
+    date_ranges: Option<Vec<DateRangeInput>>,
 }
 
-#[derive(sqlx::FromRow, Clone)]
-#[derive(Debug)]
+impl TokenInput {
+    fn whitelist(&self) -> Result<Option<Whitelist>> {
+        if self.album_ids.is_none()
+            && self.folder_ids.is_none()
+            && self.person_ids.is_none()
+            && self.keyword_ids.is_none()
+            && self.date_ranges.is_none()
+        {
+            return Ok(None);
+        }
+        let whitelist = Whitelist {
+            albums: self.album_ids.clone().unwrap_or_default(),
+            folders: self.folder_ids.clone().unwrap_or_default(),
+            people: self.person_ids.clone().unwrap_or_default(),
+            keywords: self.keyword_ids.clone().unwrap_or_default(),
+            date_ranges: self
+                .date_ranges
+                .iter()
+                .flatten()
+                .map(|r| DateRange {
+                    from: r.from.clone(),
+                    to: r.to.clone(),
+                })
+                .collect(),
+        };
+        whitelist.validate()?;
+        Ok(Some(whitelist))
+    }
+
+    /// Tokens which can only share albums are limited to creating viewer
+    /// tokens, whitelisting albums they can see themselves
+    pub async fn authorize(&self, ctx: &Context<'_>) -> AGResult<()> {
+        if ctx.data::<Token>()?.can(Permission::ManageTokens) {
+            return Ok(());
+        }
+        if self.role != Role::Viewer {
+            return Err(XpozError::Forbidden.extend());
+        }
+
+        let album_ids = match &self.album_ids {
+            Some(ids)
+                if self.folder_ids.is_none()
+                    && self.person_ids.is_none()
+                    && self.keyword_ids.is_none()
+                    && self.date_ranges.is_none() =>
+            {
+                ids
+            }
+            _ => {
+                let message = "Shared tokens can only whitelist albums".to_string();
+                return Err(XpozError::Validation(message).extend());
+            }
+        };
+
+        let libraries = ctx.data::<Libraries>()?;
+        for id in album_ids {
+            let (library, uuid) = libraries.resolve(id).map_err(graphql_error)?;
+            let album = loaders(ctx, &library.name)?
+                .albums
+                .load_one(uuid.to_string())
+                .await
+                .map_err(graphql_error)?;
+            if album.is_none() {
+                return Err(XpozError::Forbidden.extend());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Clone, Debug)]
 pub struct Token {
     name: String,
     session_bound: bool,
-    pub admin: bool,
+    pub role: Role,
     session_id: Option<String>,
     token: String,
     pub whitelist: Option<String>,
@@ -33,7 +115,7 @@ impl Token {
         Self {
             name: "Anonymous".to_string(),
             session_bound: true,
-            admin: false,
+            role: Role::Viewer,
             session_id: None,
             token: nanoid!(),
             whitelist: None,
@@ -41,15 +123,17 @@ impl Token {
         }
     }
 
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.permissions().contains(&permission)
+    }
+
     pub fn whitelist(&self) -> AllowedAlbumIds {
-        match &self.whitelist {
-            None => None,
-            Some(v) => {
-                let json: Result<AllowedAlbumIds> =
-                    serde_json::from_str(&v).map_err(anyhow::Error::from);
-                json.map_or_else(|_| None, |v| v)
-            }
-        }
+        self.whitelist.as_deref().map(Whitelist::from_json)
+    }
+
+    /// The whitelist of the token as it applies to a library
+    pub fn whitelist_for(&self, library: &Library) -> AllowedAlbumIds {
+        self.whitelist().map(|wl| wl.for_library(library))
     }
 }
 
@@ -64,8 +148,15 @@ impl Token {
     async fn session_bound(&self) -> &bool {
         &self.session_bound
     }
-    async fn admin(&self) -> &bool {
-        &self.admin
+    async fn role(&self) -> &Role {
+        &self.role
+    }
+    async fn permissions(&self) -> Vec<Permission> {
+        self.role.permissions().to_vec()
+    }
+    #[graphql(deprecation = "Use role or permissions instead")]
+    async fn admin(&self) -> bool {
+        self.role == Role::Owner
     }
     async fn session_id(&self) -> &Option<String> {
         &self.session_id
@@ -74,92 +165,129 @@ impl Token {
         &self.created_at
     }
     async fn whitelisted_albums(&self, ctx: &Context<'_>) -> AGResult<Option<Vec<Album>>> {
-        if let None = &self.whitelist {
+        if self.whitelist.is_none() {
             return Ok(None);
         }
-        Ok(Some(my_albums(
-            &ctx.data::<Databases>()?.photos,
-            ctx.data::<Vec<Entity>>()?,
-            &self.whitelist(),
-            None,
-        ).await?))
+        let mut albums: Vec<Album> = vec![];
+        for library in ctx.data::<Libraries>()?.iter() {
+            let whitelist = match self.whitelist_for(library) {
+                Some(wl) => wl,
+                None => continue,
+            };
+            let loaders = loaders(ctx, &library.name)?;
+
+            let mut found = loaders
+                .albums
+                .load_many(whitelist.albums.iter().cloned())
+                .await
+                .map_err(graphql_error)?;
+            let mut in_folders = loaders
+                .folder_albums
+                .load_many(whitelist.folders.iter().cloned())
+                .await
+                .map_err(graphql_error)?;
+
+            let listed = whitelist
+                .albums
+                .iter()
+                .filter_map(|uuid| found.remove(uuid))
+                .chain(
+                    whitelist
+                        .folders
+                        .iter()
+                        .filter_map(|uuid| in_folders.remove(uuid))
+                        .flatten(),
+                );
+
+            // an album can be whitelisted along with the folder it's in
+            let mut library_albums: Vec<Album> = vec![];
+            for album in listed {
+                if !library_albums.iter().any(|a| a.id == album.id) {
+                    library_albums.push(album);
+                }
+            }
+            albums.append(&mut library_albums);
+        }
+        Ok(Some(albums))
+    }
+    async fn whitelisted_folder_ids(&self) -> Option<Vec<String>> {
+        self.whitelist().map(|wl| wl.folders)
+    }
+    async fn whitelisted_person_ids(&self) -> Option<Vec<String>> {
+        self.whitelist().map(|wl| wl.people)
+    }
+    async fn whitelisted_keyword_ids(&self) -> Option<Vec<String>> {
+        self.whitelist().map(|wl| wl.keywords)
+    }
+    async fn whitelisted_date_ranges(&self) -> Option<Vec<DateRange>> {
+        self.whitelist().map(|wl| wl.date_ranges)
     }
 }
 
-pub async fn create_token(pool: &SqlitePool, input: TokenInput) -> Result<Option<Token>> {
+pub async fn create_token(
+    pool: &SqlitePool,
+    manager: &Token,
+    input: TokenInput,
+) -> Result<Option<Token>> {
     let token = nanoid!();
-
-    let mut builder = SqlBuilder::insert_into("tokens");
-    builder.field("session_bound").field("admin").field("token");
-
-    let mut values = vec![
-        bool_to_insert_string(input.session_bound),
-        bool_to_insert_string(input.admin),
-        quote(&token),
-    ];
-
-    builder.field("name");
-    values.push(quote(input.name));
-
-    if let Some(w) = input.album_ids {
-        let album_ids = serde_json::to_string(&w)?;
-        builder.field("whitelist");
-        values.push(quote(&album_ids));
-    }
-
-    builder.values(&values);
-
-    query(builder.sql()?.as_str()).execute(pool).await?;
-
-    let mut finder = SqlBuilder::select_from("tokens");
-    finder.and_where("token = ?".bind(&token));
-
-    let result = query_as::<_, Token>(finder.sql()?.as_str())
-        .fetch_optional(pool)
-        .await?;
-
-    Ok(result)
+    let whitelist = input.whitelist()?.map(|w| w.to_json()).transpose()?;
+
+    query(
+        "INSERT INTO tokens (name, session_bound, role, token, whitelist, created_by) \
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&input.name)
+    .bind(input.session_bound)
+    .bind(input.role)
+    .bind(&token)
+    .bind(&whitelist)
+    .bind(&manager.token)
+    .execute(pool)
+    .await?;
+
+    get_token(pool, manager, &token).await
 }
 
-pub async fn update_token(pool: &SqlitePool, token: &str, input: TokenInput) -> Result<Option<Token>> {
-    let mut builder = SqlBuilder::update_table("tokens");
-
-    builder.set("name", quote(&input.name))
-        .set("session_bound", bool_to_insert_string(input.session_bound))
-        .set("admin", bool_to_insert_string(input.admin));
-
-    if let Some(w) = input.album_ids {
-        let album_ids = serde_json::to_string(&w)?;
-        builder.set("whitelist", quote(&album_ids));
-    } else {
-        builder.set("whitelist", "NULL");
-    }
-
-    builder.and_where("token = ?".bind(&token));
-
-    query(builder.sql()?.as_str()).execute(pool).await?;
-
-    let mut finder = SqlBuilder::select_from("tokens");
-    finder.and_where("token = ?".bind(&token));
-
-    let result = query_as::<_, Token>(finder.sql()?.as_str())
-        .fetch_optional(pool)
-        .await?;
-
-    Ok(result)
+pub async fn update_token(
+    pool: &SqlitePool,
+    manager: &Token,
+    token: &str,
+    input: TokenInput,
+) -> Result<Option<Token>> {
+    let whitelist = input.whitelist()?.map(|w| w.to_json()).transpose()?;
+
+    query(
+        "UPDATE tokens SET name = ?, session_bound = ?, role = ?, whitelist = ? \
+        WHERE token = ? AND (? = 1 OR created_by = ?)",
+    )
+    .bind(&input.name)
+    .bind(input.session_bound)
+    .bind(input.role)
+    .bind(&whitelist)
+    .bind(token)
+    .bind(manager.can(Permission::ManageTokens))
+    .bind(&manager.token)
+    .execute(pool)
+    .await?;
+
+    get_token(pool, manager, token).await
 }
 
-pub async fn delete_token(pool: &SqlitePool, token: String) -> Result<Option<Token>> {
-    let existing = get_token(pool, &token).await?;
+pub async fn delete_token(
+    pool: &SqlitePool,
+    manager: &Token,
+    token: String,
+) -> Result<Option<Token>> {
+    let existing = get_token(pool, manager, &token).await?;
 
-    if let None = existing {
+    if existing.is_none() {
         return Ok(None);
     }
 
-    let mut builder = SqlBuilder::delete_from("tokens");
-    builder.and_where("token = ?".bind(&token));
-
-    let result = query(builder.sql()?.as_str()).execute(pool).await?;
+    let result = query("DELETE FROM tokens WHERE token = ?")
+        .bind(&token)
+        .execute(pool)
+        .await?;
 
     if result.rows_affected() < 1 {
         Ok(None)
@@ -168,24 +296,28 @@ pub async fn delete_token(pool: &SqlitePool, token: String) -> Result<Option<Tok
     }
 }
 
-async fn get_token(pool: &SqlitePool, token: &str) -> Result<Option<Token>> {
-    let mut builder = SqlBuilder::select_from("tokens");
-    builder.and_where("token = ?".bind(&token));
-
-    let result = query_as::<_, Token>(builder.sql()?.as_str())
-        .fetch_optional(pool)
-        .await?;
+async fn get_token(pool: &SqlitePool, manager: &Token, token: &str) -> Result<Option<Token>> {
+    let result =
+        query_as::<_, Token>("SELECT * FROM tokens WHERE token = ? AND (? = 1 OR created_by = ?)")
+            .bind(token)
+            .bind(manager.can(Permission::ManageTokens))
+            .bind(&manager.token)
+            .fetch_optional(pool)
+            .await?;
 
     Ok(result)
 }
 
-pub async fn tokens(pool: &SqlitePool) -> Result<Vec<Token>> {
-    let mut builder = SqlBuilder::select_from("tokens");
-    builder.order_by("created_at", true);
-
-    let records = query_as::<_, Token>(builder.sql()?.as_str())
-        .fetch_all(pool)
-        .await?;
+/// Tokens which can't manage all tokens only get to see, change and remove
+/// the ones they have created
+pub async fn tokens(pool: &SqlitePool, manager: &Token) -> Result<Vec<Token>> {
+    let records = query_as::<_, Token>(
+        "SELECT * FROM tokens WHERE ? = 1 OR created_by = ? ORDER BY created_at DESC",
+    )
+    .bind(manager.can(Permission::ManageTokens))
+    .bind(&manager.token)
+    .fetch_all(pool)
+    .await?;
 
     Ok(records)
 }
@@ -194,25 +326,18 @@ pub async fn find_valid_token(
     pool: &SqlitePool,
     session_id: &str,
     token: &str,
-    admin: bool,
+    owner: bool,
 ) -> Option<Token> {
-    let mut builder = SqlBuilder::select_from("tokens");
-    builder.and_where("token = ?".bind(&token));
-
-    if admin {
-        builder.and_where_eq("admin", 1);
-    }
-
-    builder.and_where(
-        "session_bound = 0 OR (session_bound = 1 AND (session_id IS NULL OR session_id = ?))"
-            .bind(&session_id),
-    );
-
-    builder.limit(1);
-
-    let result = query_as::<_, Token>(builder.sql().expect("Failed to build token query").as_str())
-        .fetch_optional(pool)
-        .await;
+    let result = query_as::<_, Token>(
+        "SELECT * FROM tokens WHERE token = ? AND (role = 'owner' OR ? = 0) \
+        AND (session_bound = 0 OR (session_bound = 1 AND (session_id IS NULL OR session_id = ?))) \
+        LIMIT 1",
+    )
+    .bind(token)
+    .bind(owner)
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await;
 
     match result {
         Ok(result) => {
@@ -229,11 +354,9 @@ pub async fn find_valid_token(
 }
 
 async fn consume_token(pool: &SqlitePool, token: &str, session_id: &str) {
-    let update = SqlBuilder::update_table("tokens")
-        .set("session_id", &quote(session_id))
-        .and_where("token = ?".bind(&token))
-        .sql()
-        .expect("Failed SQL query when consuming auth token");
-
-    let _ = query(&update).execute(pool).await;
+    let _ = query("UPDATE tokens SET session_id = ? WHERE token = ?")
+        .bind(session_id)
+        .bind(token)
+        .execute(pool)
+        .await;
 }
@@ -0,0 +1,430 @@
+use super::{
+    assets::album_join_tables, dates::local_capture_date, entities::Entity, errors::XpozError,
+    keywords::keyword_assets, libraries::Library, model::Model,
+};
+use anyhow::Result;
+use async_graphql::Object;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sql_builder::{arg::SqlArg, bind::Bind};
+
+/// An inclusive range of capture dates, formatted as YYYY-MM-DD
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DateRange {
+    pub from: String,
+    pub to: String,
+}
+
+impl DateRange {
+    fn is_valid(&self) -> bool {
+        let date = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").is_ok();
+        date(&self.from) && date(&self.to)
+    }
+}
+
+#[Object]
+impl DateRange {
+    async fn from(&self) -> &String {
+        &self.from
+    }
+    async fn to(&self) -> &String {
+        &self.to
+    }
+}
+
+/// The albums, folders, people, keywords and date ranges a token has
+/// access to. Whitelisting a folder gives access to all of its albums and
+/// subfolders, and whitelisting a keyword to all assets tagged with it,
+/// including the ones added later on
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Whitelist {
+    #[serde(default)]
+    pub albums: Vec<String>,
+    #[serde(default)]
+    pub folders: Vec<String>,
+    #[serde(default)]
+    pub people: Vec<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub date_ranges: Vec<DateRange>,
+}
+
+// Tokens created before folders could be whitelisted store a plain list of
+// album uuids
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredWhitelist {
+    Albums(Vec<String>),
+    Whitelist(Whitelist),
+}
+
+impl Whitelist {
+    /// Reads a stored whitelist. One which can't be read gives access to
+    /// nothing, rather than leaving the token unrestricted
+    pub fn from_json(json: &str) -> Self {
+        match serde_json::from_str(json) {
+            Ok(StoredWhitelist::Albums(albums)) => Self {
+                albums,
+                ..Default::default()
+            },
+            Ok(StoredWhitelist::Whitelist(wl)) => wl,
+            Err(e) => {
+                log::error!("Can't read the whitelist of a token, denying access: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Fails for ids which aren't Photos uuids and dates which aren't
+    /// formatted as YYYY-MM-DD, before the whitelist gets stored
+    pub fn validate(&self) -> Result<()> {
+        let ids = self
+            .albums
+            .iter()
+            .chain(self.folders.iter())
+            .chain(self.people.iter())
+            .chain(self.keywords.iter());
+        for id in ids {
+            let uuid = id.rsplit(':').next().unwrap_or_default();
+            if !is_uuid(uuid) {
+                return Err(XpozError::Validation(format!("Invalid id {}", id)).into());
+            }
+        }
+
+        for range in &self.date_ranges {
+            if !range.is_valid() {
+                return Err(XpozError::Validation(format!(
+                    "Invalid date range {} - {}",
+                    range.from, range.to
+                ))
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The part of the whitelist which applies to a library, with the ids
+    /// of its albums, folders, people and keywords turned into Photos uuids.
+    /// Date ranges apply to all libraries. Ids and dates which couldn't
+    /// have been whitelisted are left out, as they end up in sql
+    pub fn for_library(&self, library: &Library) -> Self {
+        let uuids = |ids: &Vec<String>| -> Vec<String> {
+            ids.iter()
+                .filter_map(|id| library.uuid(id))
+                .filter(|uuid| is_uuid(uuid))
+                .map(String::from)
+                .collect()
+        };
+        Self {
+            albums: uuids(&self.albums),
+            folders: uuids(&self.folders),
+            people: uuids(&self.people),
+            keywords: uuids(&self.keywords),
+            date_ranges: self
+                .date_ranges
+                .iter()
+                .filter(|r| r.is_valid())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Sql conditions on ZGENERICALBUM matching the whitelisted albums and
+    /// the albums in whitelisted folders
+    pub fn album_conditions(&self) -> String {
+        format!(
+            "(ZUUID IN ({}) OR ZPARENTFOLDER IN ({}))",
+            bound_list(&self.albums),
+            self.folder_tree()
+        )
+    }
+
+    /// A subquery selecting the primary keys of all whitelisted folders
+    /// and their descendants
+    pub fn folder_tree(&self) -> String {
+        format!(
+            "WITH RECURSIVE tree(pk) AS (\
+                SELECT Z_PK FROM ZGENERICALBUM WHERE ZUUID IN ({}) \
+                UNION SELECT f.Z_PK FROM ZGENERICALBUM f JOIN tree ON f.ZPARENTFOLDER = tree.pk\
+            ) SELECT pk FROM tree",
+            bound_list(&self.folders)
+        )
+    }
+
+    /// Sql conditions on the `assets` table matching the whitelisted date
+    /// ranges, if there are any
+    pub fn date_range_conditions(&self) -> Option<String> {
+        if self.date_ranges.is_empty() {
+            return None;
+        }
+
+        let ranges: Vec<String> = self
+            .date_ranges
+            .iter()
+            .map(|r| format!("{} BETWEEN ? AND ?", local_capture_date()).binds(&[&r.from, &r.to]))
+            .collect();
+
+        Some(format!("({})", ranges.join(" OR ")))
+    }
+
+    /// Sql conditions on the `assets` table matching the assets in
+    /// whitelisted albums and folders, of whitelisted people, tagged with
+    /// whitelisted keywords and within whitelisted date ranges
+    pub fn asset_conditions(&self, library: &Library) -> Result<String> {
+        self.conditions_on_assets(&library.entities(), &library.model())
+    }
+
+    /// Sql conditions on the `assets` table of a library with the given
+    /// entities and database model
+    fn conditions_on_assets(&self, cache: &[Entity], model: &Model) -> Result<String> {
+        let mut conditions = vec![];
+
+        if !self.albums.is_empty() || !self.folders.is_empty() {
+            let joins = album_join_tables(cache)?;
+            conditions.push(format!(
+                "assets.Z_PK IN (SELECT joins.{asset_fk} FROM {join_table} \
+                WHERE joins.{album_fk} IN (SELECT Z_PK FROM ZGENERICALBUM WHERE {albums}))",
+                asset_fk = joins.2,
+                join_table = joins.0,
+                album_fk = joins.1,
+                albums = self.album_conditions()
+            ));
+        }
+
+        if !self.people.is_empty() {
+            let conditions_on_people =
+                format!("people.ZPERSONUUID IN ({})", bound_list(&self.people));
+            conditions.push(format!(
+                "assets.Z_PK IN ({})",
+                model.person_assets(&conditions_on_people)
+            ));
+        }
+
+        if !self.keywords.is_empty() {
+            let conditions_on_keywords = format!("kw.ZUUID IN ({})", bound_list(&self.keywords));
+            // libraries without keywords have no keyword join table to query
+            if let Ok(subquery) = keyword_assets(cache, &conditions_on_keywords) {
+                conditions.push(format!("assets.Z_PK IN ({})", subquery));
+            }
+        }
+
+        if let Some(dates) = self.date_range_conditions() {
+            conditions.push(dates);
+        }
+
+        if conditions.is_empty() {
+            return Ok("0".to_string());
+        }
+
+        let conditions: Vec<String> = conditions.iter().map(|c| format!("({})", c)).collect();
+        Ok(format!("({})", conditions.join(" OR ")))
+    }
+}
+
+/// The ids as a list of sql values, e.g. for an IN clause
+fn bound_list(ids: &[String]) -> String {
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let args: Vec<&dyn SqlArg> = ids.iter().map(|id| id as &dyn SqlArg).collect();
+    placeholders.binds(&args)
+}
+
+/// Whether an id looks like the uuid of a Photos record. Ids which do can
+/// be quoted into sql safely
+pub fn is_uuid(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{Connection, NO_PARAMS};
+
+    fn select(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut statement = conn.prepare(sql).unwrap();
+        let rows = statement.query_map(NO_PARAMS, |row| row.get(0)).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    fn range(from: &str, to: &str) -> DateRange {
+        DateRange {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn album_conditions_keep_other_conditions() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE ZGENERICALBUM (Z_PK INTEGER, ZUUID TEXT, ZPARENTFOLDER INTEGER, \
+            ZTRASHEDSTATE INTEGER); \
+            INSERT INTO ZGENERICALBUM VALUES (1, 'folder', NULL, 0), (2, 'subfolder', 1, 0), \
+            (3, 'in-subfolder', 2, 0), (4, 'trashed-in-folder', 1, 1), (5, 'album', NULL, 0), \
+            (6, 'trashed-album', NULL, 1), (7, 'other', NULL, 0);",
+        )
+        .unwrap();
+
+        let whitelist = Whitelist {
+            albums: vec!["album".to_string(), "trashed-album".to_string()],
+            folders: vec!["folder".to_string()],
+            ..Default::default()
+        };
+        let sql = format!(
+            "SELECT ZUUID FROM ZGENERICALBUM WHERE ZTRASHEDSTATE < 1 AND {} ORDER BY Z_PK",
+            whitelist.album_conditions()
+        );
+
+        assert_eq!(
+            select(&conn, &sql),
+            vec!["subfolder", "in-subfolder", "album"]
+        );
+    }
+
+    #[test]
+    fn date_range_conditions_keep_other_conditions() {
+        let conn = Connection::open_in_memory().unwrap();
+        // 2020-01-01 and 2021-01-01 00:00 UTC, relative to the Apple epoch
+        conn.execute_batch(
+            "CREATE TABLE assets (Z_PK INTEGER, ZDATECREATED REAL, ZHIDDEN INTEGER); \
+            CREATE TABLE ZADDITIONALASSETATTRIBUTES (ZASSET INTEGER, ZTIMEZONEOFFSET INTEGER); \
+            INSERT INTO assets VALUES (1, 599529600, 0), (2, 599529600, 1), (3, 631152000, 0), \
+            (4, 631152000, 1), (5, 0, 0); \
+            INSERT INTO ZADDITIONALASSETATTRIBUTES VALUES (3, -3600);",
+        )
+        .unwrap();
+
+        let whitelist = Whitelist {
+            date_ranges: vec![
+                range("2020-01-01", "2020-01-01"),
+                range("2020-12-31", "2020-12-31"),
+            ],
+            ..Default::default()
+        };
+        let sql = format!(
+            "SELECT CAST(Z_PK AS TEXT) FROM assets WHERE ZHIDDEN < 1 AND {} ORDER BY Z_PK",
+            whitelist.date_range_conditions().unwrap()
+        );
+
+        // the third asset was taken on new year's eve in its own timezone
+        assert_eq!(select(&conn, &sql), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn asset_conditions_keep_other_conditions() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE assets (Z_PK INTEGER, ZDATECREATED REAL, ZHIDDEN INTEGER); \
+            CREATE TABLE ZADDITIONALASSETATTRIBUTES (ZASSET INTEGER, ZTIMEZONEOFFSET INTEGER); \
+            CREATE TABLE ZGENERICALBUM (Z_PK INTEGER, ZUUID TEXT, ZPARENTFOLDER INTEGER); \
+            CREATE TABLE Z_2ASSETS (Z_2ALBUMS INTEGER, Z_3ASSETS INTEGER); \
+            INSERT INTO assets VALUES (1, 0, 0), (2, 0, 1), (3, 599529600, 0), \
+            (4, 599529600, 1), (5, 631152000, 0); \
+            INSERT INTO ZGENERICALBUM VALUES (1, 'album', NULL); \
+            INSERT INTO Z_2ASSETS VALUES (1, 1), (1, 2);",
+        )
+        .unwrap();
+
+        let entities = vec![Entity::new(2, "Album"), Entity::new(3, "Asset")];
+        let model = Model {
+            version: 15000,
+            assets: "ZASSET",
+            face_asset: "ZASSET",
+            face_person: "ZPERSON",
+            custom_key_asset: "ZCUSTOMKEYASSET",
+        };
+        let whitelist = Whitelist {
+            albums: vec!["album".to_string()],
+            date_ranges: vec![range("2020-01-01", "2020-01-01")],
+            ..Default::default()
+        };
+        let sql = format!(
+            "SELECT CAST(Z_PK AS TEXT) FROM assets WHERE ZHIDDEN < 1 AND {} ORDER BY Z_PK",
+            whitelist.conditions_on_assets(&entities, &model).unwrap()
+        );
+
+        assert_eq!(select(&conn, &sql), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn binds_ids_as_sql_values() {
+        let ids = vec!["0A1B".to_string(), "x') OR 1=1 --".to_string()];
+        assert_eq!(bound_list(&ids), "'0A1B', 'x'') OR 1=1 --'");
+        assert_eq!(bound_list(&[]), "");
+    }
+
+    #[test]
+    fn no_date_range_conditions_without_ranges() {
+        assert!(Whitelist::default().date_range_conditions().is_none());
+    }
+
+    #[test]
+    fn validates_ids_and_dates() {
+        let valid = Whitelist {
+            albums: vec!["0A1B-2C3D".to_string(), "library:0A1B-2C3D".to_string()],
+            date_ranges: vec![range("2020-02-29", "2020-03-01")],
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+
+        let quoted = Whitelist {
+            people: vec!["x') OR 1=1 --".to_string()],
+            ..Default::default()
+        };
+        assert!(quoted.validate().is_err());
+
+        let empty = Whitelist {
+            keywords: vec!["library:".to_string()],
+            ..Default::default()
+        };
+        assert!(empty.validate().is_err());
+
+        let dates = Whitelist {
+            date_ranges: vec![range("2021-02-29", "2021-03-01")],
+            ..Default::default()
+        };
+        assert!(dates.validate().is_err());
+    }
+
+    #[test]
+    fn reads_plain_album_lists() {
+        let whitelist = Whitelist::from_json(r#"["0A1B", "2C3D"]"#);
+        assert_eq!(whitelist.albums, vec!["0A1B", "2C3D"]);
+        assert!(whitelist.folders.is_empty());
+    }
+
+    #[test]
+    fn reads_whitelists() {
+        let whitelist = Whitelist::from_json(
+            r#"{"folders": ["0A1B"], "date_ranges": [{"from": "2020-01-01", "to": "2020-12-31"}]}"#,
+        );
+        assert!(whitelist.albums.is_empty());
+        assert_eq!(whitelist.folders, vec!["0A1B"]);
+        assert_eq!(whitelist.date_ranges[0].to, "2020-12-31");
+    }
+
+    #[test]
+    fn unreadable_whitelists_give_access_to_nothing() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE ZGENERICALBUM (Z_PK INTEGER, ZUUID TEXT, ZPARENTFOLDER INTEGER); \
+            INSERT INTO ZGENERICALBUM VALUES (1, 'folder', NULL), (2, 'album', 1);",
+        )
+        .unwrap();
+
+        for json in &["", "{", r#"{"albums": "0A1B"}"#, "42"] {
+            let whitelist = Whitelist::from_json(json);
+            let sql = format!(
+                "SELECT ZUUID FROM ZGENERICALBUM WHERE {}",
+                whitelist.album_conditions()
+            );
+            assert!(select(&conn, &sql).is_empty());
+            assert_eq!(whitelist.date_range_conditions(), None);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use crate::settings::{Photos, Settings};
+use anyhow::{anyhow, Result};
+use sql_builder::prelude::*;
+use sqlx::{
+    query_as,
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+    ConnectOptions, Connection, Executor,
+};
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+// Tables of the Photos database xpoz reads from. Join tables are named
+// after the ids of the entities they join, so they're matched separately
+const TABLES: [&str; 17] = [
+    "Z_METADATA",
+    "Z_PRIMARYKEY",
+    "ZASSET",
+    "ZGENERICASSET",
+    "ZGENERICALBUM",
+    "ZADDITIONALASSETATTRIBUTES",
+    "ZEXTENDEDATTRIBUTES",
+    "ZASSETDESCRIPTION",
+    "ZUNMANAGEDADJUSTMENT",
+    "ZINTERNALRESOURCE",
+    "ZUNIFORMTYPEIDENTIFIER",
+    "ZPERSON",
+    "ZDETECTEDFACE",
+    "ZKEYWORD",
+    "ZMOMENT",
+    "ZMEMORY",
+    "ZCLOUDSHAREDALBUMINVITATIONRECORD",
+];
+
+// Assets are the only table large enough to be worth copying incrementally
+const ASSET_TABLES: [&str; 2] = ["ZASSET", "ZGENERICASSET"];
+
+#[derive(sqlx::FromRow)]
+struct Table {
+    name: String,
+    sql: String,
+}
+
+/// Exports every configured library into a subdirectory of `destination`
+/// named after the library
+pub async fn export_all(settings: &Settings, destination: &Path) -> Result<()> {
+    for photos in settings.all_libraries() {
+        let library_destination = library_destination(destination, &photos.name)?;
+        log::info!("Exporting library '{}'", photos.name);
+        export(photos, &library_destination).await?;
+    }
+
+    Ok(())
+}
+
+/// The directory a library is exported to. Library names only need to be
+/// unique, so the ones which aren't a plain directory name are refused
+/// rather than exported somewhere outside of `destination`
+fn library_destination(destination: &Path, name: &str) -> Result<PathBuf> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(dir)), None) if dir == name => Ok(destination.join(dir)),
+        _ => Err(anyhow!(
+            "Library '{}' can't be exported, its name isn't a valid directory name",
+            name
+        )),
+    }
+}
+
+/// Copies what xpoz needs from a Photos library into `destination`, laid
+/// out like a Photos library itself, so it can be served from a machine
+/// without Photos.app by pointing the `photos` settings at it. Exporting
+/// to the same destination again only copies the assets and files which
+/// have changed since
+pub async fn export(photos: &Photos, destination: &Path) -> Result<()> {
+    let database = destination.join("database").join("Photos.sqlite");
+    std::fs::create_dir_all(database.parent().expect("Snapshot database has no parent"))?;
+
+    log::info!("Exporting the Photos database to {:?}", database);
+    export_database(photos, &database).await?;
+
+    for subdir in [
+        &photos.originals,
+        &photos.renders,
+        &photos.resized,
+        &photos.thumbs,
+        &photos.cloudsharing,
+    ]
+    .iter()
+    {
+        log::info!("Exporting {}", subdir);
+        let copied = export_files(&photos.dir_to(subdir), &destination.join(subdir))?;
+        log::info!("Copied {} new or changed files from {}", copied, subdir);
+    }
+
+    Ok(())
+}
+
+async fn tables(conn: &mut SqliteConnection, schema: &str, kind: &str) -> Result<Vec<Table>> {
+    let names: Vec<String> = TABLES.iter().map(quote).collect();
+
+    let mut select = SqlBuilder::select_from(format!("{}.sqlite_master", schema));
+    select
+        .fields(&["name", "sql"])
+        .and_where_eq("type", quote(kind))
+        .and_where_is_not_null("sql");
+
+    match kind {
+        "index" => select.and_where(format!(
+            "(tbl_name IN ({}) OR tbl_name GLOB 'Z_[0-9]*')",
+            names.join(", ")
+        )),
+        _ => select.and_where(format!(
+            "(name IN ({}) OR name GLOB 'Z_[0-9]*')",
+            names.join(", ")
+        )),
+    };
+
+    let records = query_as::<_, Table>(select.sql()?.as_str())
+        .fetch_all(conn)
+        .await?;
+
+    Ok(records)
+}
+
+/// A URI opening the database at `path` read only, so exporting can never
+/// write to the Photos database. The bundled SQLite is built with URI
+/// filenames enabled, which makes ATTACH understand them
+fn read_only_uri(path: &str) -> String {
+    let path = path
+        .replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23");
+    format!("file:{}?mode=ro", path)
+}
+
+/// Copies the tables xpoz reads from the Photos database, which is
+/// attached to the snapshot as `photos`. Tables are recreated when Photos
+/// has changed their layout since the last export
+async fn export_database(photos: &Photos, database: &Path) -> Result<()> {
+    let mut conn = SqliteConnectOptions::default()
+        .filename(database)
+        .create_if_missing(true)
+        .connect()
+        .await?;
+
+    let attach = format!(
+        "ATTACH DATABASE {} AS photos",
+        quote(read_only_uri(&photos.database_url()))
+    );
+    conn.execute(attach.as_str()).await?;
+
+    let source = tables(&mut conn, "photos", "table").await?;
+    let existing = tables(&mut conn, "main", "table").await?;
+
+    let mut tx = conn.begin().await?;
+
+    for table in &source {
+        let unchanged = existing
+            .iter()
+            .any(|t| t.name == table.name && t.sql == table.sql);
+
+        if !unchanged {
+            let drop = format!("DROP TABLE IF EXISTS main.{}", table.name);
+            tx.execute(drop.as_str()).await?;
+            tx.execute(table.sql.as_str()).await?;
+        }
+
+        if unchanged && ASSET_TABLES.contains(&table.name.as_str()) {
+            let copy = format!(
+                "INSERT OR REPLACE INTO main.{table} SELECT * FROM photos.{table} \
+                WHERE ZMODIFICATIONDATE IS NULL \
+                OR ZMODIFICATIONDATE > (SELECT IFNULL(MAX(ZMODIFICATIONDATE), 0) FROM main.{table})",
+                table = table.name
+            );
+            tx.execute(copy.as_str()).await?;
+
+            let mut delete = SqlBuilder::delete_from(format!("main.{}", table.name));
+            delete.and_where(format!(
+                "Z_PK NOT IN (SELECT Z_PK FROM photos.{})",
+                table.name
+            ));
+            tx.execute(delete.sql()?.as_str()).await?;
+        } else {
+            let delete = SqlBuilder::delete_from(format!("main.{}", table.name)).sql()?;
+            tx.execute(delete.as_str()).await?;
+
+            let copy = format!(
+                "INSERT INTO main.{table} SELECT * FROM photos.{table}",
+                table = table.name
+            );
+            tx.execute(copy.as_str()).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    let existing_indexes = tables(&mut conn, "main", "index").await?;
+    for index in tables(&mut conn, "photos", "index").await? {
+        if !existing_indexes.iter().any(|i| i.name == index.name) {
+            conn.execute(index.sql.as_str()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies the files in `source` which are missing from `destination` or
+/// have been modified since they were copied, returning how many were
+fn export_files(source: &Path, destination: &Path) -> Result<usize> {
+    let mut copied = 0;
+    if !source.exists() {
+        return Ok(copied);
+    }
+
+    for entry in WalkDir::new(source) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let target = destination.join(entry.path().strip_prefix(source)?);
+        let modified = entry.metadata()?.modified()?;
+        let outdated = match target.metadata() {
+            Ok(existing) => existing.modified()? < modified,
+            Err(_) => true,
+        };
+
+        if outdated {
+            if let Some(dir) = target.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::copy(entry.path(), &target)?;
+            copied += 1;
+        }
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::{Connection, NO_PARAMS};
+
+    #[test]
+    fn exports_libraries_into_subdirectories() {
+        let destination = Path::new("/snapshots");
+        assert_eq!(
+            library_destination(destination, "laptop").unwrap(),
+            Path::new("/snapshots/laptop")
+        );
+        for name in &["..", ".", "a/b", "/abs", "laptop/", ""] {
+            assert!(library_destination(destination, name).is_err());
+        }
+    }
+
+    #[test]
+    fn escapes_uri_characters() {
+        assert_eq!(
+            read_only_uri("/Photos Library?#100%.photoslibrary/Photos.sqlite"),
+            "file:/Photos Library%3f%23100%25.photoslibrary/Photos.sqlite?mode=ro"
+        );
+    }
+
+    #[test]
+    fn attaches_read_only() {
+        let path = std::env::temp_dir().join(format!("xpoz-export-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE ZASSET (Z_PK INTEGER);")
+            .unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        let attach = format!("ATTACH DATABASE {} AS photos", quote(read_only_uri(&path)));
+        conn.execute_batch(&attach).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM photos.ZASSET", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        let insert = conn.execute("INSERT INTO photos.ZASSET VALUES (1)", NO_PARAMS);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(count, 0);
+        assert!(insert.is_err());
+    }
+}
@@ -1,5 +1,6 @@
 mod auth;
 mod db;
+mod export;
 mod services;
 mod settings;
 mod transcoder;
@@ -7,18 +8,26 @@ mod transcoder;
 use actix_session::CookieSession;
 use actix_web::middleware::{Compress, DefaultHeaders, Logger};
 use actix_web::{web, App, HttpServer};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_graphql::{EmptySubscription, Schema as AGSchema};
 use auth::Auth;
 use db::{
-    build_pool,
-    entities::{entities, Entity},
+    build_photos_pool, build_pool,
+    entities::entities,
+    libraries::{Libraries, Library},
     migrate::migrate_database,
+    model::detect_model,
+    reload::keep_entities_fresh,
+    resources::ResourceCache,
+    search::keep_index_fresh,
     Databases, MutationRoot, QueryRoot,
 };
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use services::graphql::PersistedQueries;
 use settings::{load_settings, Settings};
 use sqlx::sqlite::{SqliteConnectOptions, SqliteSynchronous};
+use std::env::args;
+use std::path::Path;
 use std::sync::Arc;
 use transcoder::Transcoder;
 
@@ -27,7 +36,16 @@ async fn main() -> Result<()> {
     dotenv::dotenv().ok();
     env_logger::init();
 
-    let cfg = configure().await;
+    // xpoz export <destination> [config]
+    if args().nth(1).as_deref() == Some("export") {
+        let destination = args()
+            .nth(2)
+            .expect("Usage: xpoz export <destination> [config]");
+        let settings = load_settings(args().nth(3));
+        return export::export_all(&settings, Path::new(&destination)).await;
+    }
+
+    let cfg = configure().await?;
 
     let config = Arc::new(cfg.0.clone());
 
@@ -43,39 +61,72 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn configure() -> (Settings, Databases, Vec<Entity>) {
-    let settings = load_settings();
+async fn configure() -> Result<(Settings, Databases, Libraries)> {
+    let settings = load_settings(args().nth(1));
     log::debug!("{:?}", settings);
 
     migrate_database(&settings.app.database);
 
-    let app_opts = SqliteConnectOptions::default().filename(&settings.app.database_url());
+    let app_opts = SqliteConnectOptions::default().filename(settings.app.database_url());
     let app_pool = build_pool(app_opts).await;
 
-    let photos_opts = SqliteConnectOptions::default()
-        .filename(&settings.photos.database_url())
-        .read_only(true)
-        .synchronous(SqliteSynchronous::Off);
-    let photos_pool = build_pool(photos_opts).await;
-
-    let entities = entities(&photos_pool)
-        .await
-        .expect("Can't load entities from db");
+    let mut libraries = vec![];
+    for (i, photos) in settings.all_libraries().into_iter().enumerate() {
+        let photos_opts = SqliteConnectOptions::default()
+            .filename(photos.database_url())
+            .read_only(true)
+            .synchronous(SqliteSynchronous::Off);
+        let photos_pool = build_photos_pool(photos_opts, &settings.app.database_url()).await;
+
+        let model = detect_model(&photos_pool).await.with_context(|| {
+            format!(
+                "Can't open the Photos database of library '{}'",
+                photos.name
+            )
+        })?;
+
+        let entities = entities(&photos_pool, &photos.name)
+            .await
+            .with_context(|| format!("Can't load the entities of library '{}'", photos.name))?;
+
+        libraries.push(Library::new(
+            photos.clone(),
+            i == 0,
+            photos_pool,
+            model,
+            entities,
+        ));
+    }
 
-    let dbs = Databases {
-        photos: photos_pool,
-        app: app_pool,
-    };
+    let dbs = Databases { app: app_pool };
 
-    (settings, dbs, entities)
+    Ok((settings, dbs, Libraries::new(libraries)))
 }
 
-async fn run(settings: Settings, dbs: Databases, entity_cache: Vec<Entity>) -> Result<()> {
+async fn run(settings: Settings, dbs: Databases, libraries: Libraries) -> Result<()> {
     let server_settings = settings.server.clone();
+    actix_web::rt::spawn(keep_index_fresh(
+        dbs.app.clone(),
+        libraries.clone(),
+        settings.app.search_refresh_interval,
+    ));
     let schema = AGSchema::build(QueryRoot, MutationRoot, EmptySubscription)
         .data(dbs.clone())
-        .data(entity_cache)
+        .data(libraries.clone())
+        .limit_depth(settings.server.max_depth)
+        .limit_complexity(settings.server.max_complexity)
         .finish();
+    let resource_cache = Arc::new(ResourceCache::default());
+    actix_web::rt::spawn(keep_entities_fresh(
+        libraries.clone(),
+        resource_cache.clone(),
+    ));
+    let resource_cache = web::Data::from(resource_cache);
+    let persisted_queries = settings
+        .server
+        .persisted_queries
+        .as_deref()
+        .map(|path| web::Data::new(PersistedQueries::from_file(path)));
     let server = HttpServer::new(move || {
         let session = CookieSession::signed(&[0; 32])
             .secure(settings.server.ssl)
@@ -83,7 +134,9 @@ async fn run(settings: Settings, dbs: Databases, entity_cache: Vec<Entity>) -> R
         App::new()
             .data(settings.clone())
             .data(dbs.clone())
+            .data(libraries.clone())
             .data(schema.clone())
+            .app_data(resource_cache.clone())
             .wrap(Auth {})
             .wrap(session)
             .wrap(Logger::default())
@@ -94,12 +147,12 @@ async fn run(settings: Settings, dbs: Databases, entity_cache: Vec<Entity>) -> R
                     .wrap(DefaultHeaders::new().header("cache-control", "max-age=86400"))
                     .configure(services::files::config),
             )
-            .configure(services::graphql::config)
+            .configure(|cfg| services::graphql::config(cfg, &settings, persisted_queries.clone()))
             .service(
                 actix_files::Files::new("/", &settings.server.public_dir)
                     .index_file(&settings.server.index_file)
                     .use_etag(false)
-                    .use_last_modified(false)
+                    .use_last_modified(false),
             )
     });
 
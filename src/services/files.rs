@@ -1,27 +1,33 @@
-use crate::db::{assets::asset, Databases};
+use crate::db::{assets::asset, libraries::Libraries, resources::ResourceCache};
 use crate::settings::Settings;
 use actix_files as fs;
 use actix_web::{get, web, Either, HttpResponse};
 
-#[get("/{variant}/{uuid}")]
+#[get("/{variant}/{id}")]
 async fn get_asset(
-    web::Path((variant, uuid)): web::Path<(String, String)>,
+    web::Path((variant, id)): web::Path<(String, String)>,
     settings: web::Data<Settings>,
-    dbs: web::Data<Databases>,
+    libraries: web::Data<Libraries>,
+    cache: web::Data<ResourceCache>,
 ) -> Either<fs::NamedFile, HttpResponse> {
     let settings = settings.into_inner();
-    let dbs = dbs.into_inner();
-    if let Ok(Some(asset)) = asset(&dbs.photos, &uuid).await {
-        let file = match variant.as_str() {
-            "thumb" => asset.thumb(&settings),
-            "render" => asset.render(&settings),
-            "resized" => asset.resized(&settings),
-            "video" => asset.video(&settings),
-            _ => asset.original(&settings),
-        };
+    let libraries = libraries.into_inner();
+    if let Ok((library, uuid)) = libraries.resolve(&id) {
+        if let Ok(Some(asset)) = asset(library, uuid).await {
+            let resources = cache.resources(library, asset.id).await.unwrap_or_default();
+            let photos = &library.settings;
+            let file = match variant.as_str() {
+                "thumb" => asset.thumb(photos, &resources),
+                "render" => asset.render(photos, &resources),
+                "best" => asset.best(photos, &resources),
+                "resized" => asset.resized(photos, &resources),
+                "video" => asset.video(&settings),
+                _ => asset.original(photos),
+            };
 
-        if let Ok(f) = file {
-            return Either::A(f.use_etag(false).use_last_modified(false));
+            if let Ok(f) = file {
+                return Either::A(f.use_etag(false).use_last_modified(false));
+            }
         }
     }
 
@@ -1,11 +1,38 @@
-use crate::db::tokens::Token;
-use crate::{db::Schema, settings::load_settings};
+use crate::db::{libraries::Libraries, loaders::Loaders, tokens::Token};
+use crate::{db::Schema, settings::Settings};
 use actix_web::{get, post, web, HttpRequest, HttpResponse, Result as AWResult};
 use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::{Response as AGResponse, ServerError};
 use async_graphql_actix_web::{Request, Response};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// The sha256 hashes of the only queries accepted when persisted queries
+/// are enabled, i.e. the ones the frontend makes
+pub struct PersistedQueries(HashSet<String>);
+
+impl PersistedQueries {
+    pub fn from_file(path: &str) -> Self {
+        let json = std::fs::read_to_string(path).expect("Can't read the persisted queries file");
+        let queries: HashMap<String, String> =
+            serde_json::from_str(&json).expect("Persisted queries file is not valid json");
+        Self(queries.into_keys().collect())
+    }
+
+    fn allows(&self, query: &str) -> bool {
+        let hash = hex::encode(Sha256::digest(query.as_bytes()));
+        self.0.contains(&hash)
+    }
+}
 
 #[post("/api")]
-async fn api(schema: web::Data<Schema>, req: HttpRequest, gql_req: Request) -> Response {
+async fn api(
+    schema: web::Data<Schema>,
+    libraries: web::Data<Libraries>,
+    persisted: Option<web::Data<PersistedQueries>>,
+    req: HttpRequest,
+    gql_req: Request,
+) -> Response {
     // std::thread::sleep_ms(500);
     let token = req
         .head()
@@ -14,7 +41,14 @@ async fn api(schema: web::Data<Schema>, req: HttpRequest, gql_req: Request) -> R
         .expect("Can't get api access token")
         .clone();
     let mut gql_request = gql_req.into_inner();
-    gql_request = gql_request.data(token);
+    if let Some(persisted) = persisted {
+        if !persisted.allows(&gql_request.query) {
+            let error = ServerError::new("Only persisted queries are allowed");
+            return AGResponse::from_errors(vec![error]).into();
+        }
+    }
+    let loaders = Loaders::new(&libraries, &token);
+    gql_request = gql_request.data(token).data(loaders);
     schema.execute(gql_request).await.into()
 }
 
@@ -27,10 +61,18 @@ async fn graphiql() -> AWResult<HttpResponse> {
         )))
 }
 
-pub fn config(cfg: &mut web::ServiceConfig) {
+/// Sets up the api. The persisted queries are read once by the caller and
+/// shared between workers
+pub fn config(
+    cfg: &mut web::ServiceConfig,
+    settings: &Settings,
+    persisted: Option<web::Data<PersistedQueries>>,
+) {
     cfg.service(api);
-    let settings = load_settings();
     if settings.server.graphiql {
         cfg.service(graphiql);
     }
+    if let Some(persisted) = persisted {
+        cfg.app_data(persisted);
+    }
 }
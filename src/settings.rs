@@ -1,7 +1,6 @@
 use config::{Config, ConfigError, Environment, File, FileFormat};
 use serde::Deserialize;
 use shellexpand::tilde;
-use std::env::args;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Server {
@@ -9,24 +8,63 @@ pub struct Server {
     pub public_dir: String,
     pub index_file: String,
     pub graphiql: bool,
+    pub max_depth: usize,
+    pub max_complexity: usize,
+    #[serde(default)]
+    pub persisted_queries: Option<String>,
     pub ssl: bool,
     pub cert: String,
     pub key: String,
 }
 
+/// A Photos library. The media directories default to the ones Photos.app
+/// uses, so additional libraries only need a name and their locations
 #[derive(Clone, Debug, Deserialize)]
 pub struct Photos {
+    #[serde(default = "default_name")]
+    pub name: String,
     pub library: String,
     pub database: String,
+    #[serde(default = "default_originals")]
     pub originals: String,
+    #[serde(default = "default_renders")]
     pub renders: String,
+    #[serde(default = "default_resized")]
     pub resized: String,
+    #[serde(default = "default_thumbs")]
     pub thumbs: String,
+    #[serde(default = "default_cloudsharing")]
+    pub cloudsharing: String,
+}
+
+fn default_name() -> String {
+    "default".to_string()
+}
+
+fn default_originals() -> String {
+    "originals".to_string()
+}
+
+fn default_renders() -> String {
+    "resources/renders".to_string()
+}
+
+fn default_resized() -> String {
+    "resources/derivatives".to_string()
+}
+
+fn default_thumbs() -> String {
+    "resources/derivatives/masters".to_string()
+}
+
+fn default_cloudsharing() -> String {
+    "resources/cloudsharing".to_string()
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct App {
     pub database: String,
+    pub search_refresh_interval: u64,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -52,7 +90,7 @@ impl App {
 }
 
 impl Photos {
-    fn dir_to(&self, subdir: &String) -> std::path::PathBuf {
+    pub fn dir_to(&self, subdir: &String) -> std::path::PathBuf {
         let mut path = std::path::PathBuf::new();
         path.push(tilde(&self.library).to_string());
         path.push(subdir);
@@ -63,16 +101,8 @@ impl Photos {
         self.dir_to(&self.originals)
     }
 
-    pub fn renders_dir(&self) -> std::path::PathBuf {
-        self.dir_to(&self.renders)
-    }
-
-    pub fn resized_dir(&self) -> std::path::PathBuf {
-        self.dir_to(&self.resized)
-    }
-
-    pub fn thumbs_dir(&self) -> std::path::PathBuf {
-        self.dir_to(&self.thumbs)
+    pub fn cloudsharing_dir(&self) -> std::path::PathBuf {
+        self.dir_to(&self.cloudsharing)
     }
 
     pub fn database_url(&self) -> String {
@@ -83,6 +113,8 @@ impl Photos {
 #[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
     pub photos: Photos,
+    #[serde(default)]
+    pub libraries: Vec<Photos>,
     pub server: Server,
     pub app: App,
     pub media: Media,
@@ -95,17 +127,90 @@ impl Settings {
         config.merge(File::from_str(&default, FileFormat::Yaml))?;
         config.merge(File::with_name(filename).required(false))?;
         config.merge(Environment::with_prefix("XPOZ").separator("__"))?;
-        config.try_into()
+        let settings: Self = config.try_into()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Makes sure every library has a name records can be qualified with.
+    /// Ids of records look like `<library>:<uuid>`, so names can't contain
+    /// a colon and have to be unique
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut names: Vec<&str> = vec![];
+        for photos in self.all_libraries() {
+            let name = photos.name.as_str();
+            if name.is_empty() || name.contains(':') {
+                return Err(ConfigError::Message(format!(
+                    "Library name '{}' of {} is invalid. Names can't be empty or contain ':'",
+                    name, photos.library
+                )));
+            }
+            if names.contains(&name) {
+                return Err(ConfigError::Message(format!(
+                    "Library {} needs a unique name, '{}' is taken already",
+                    photos.library, name
+                )));
+            }
+            names.push(name);
+        }
+        Ok(())
     }
 
     pub fn default_file() -> &'static str {
         "settings.yml"
     }
+
+    /// All the libraries being served, starting with the default one
+    pub fn all_libraries(&self) -> Vec<&Photos> {
+        std::iter::once(&self.photos)
+            .chain(self.libraries.iter())
+            .collect()
+    }
 }
 
-pub fn load_settings() -> Settings {
-    let config_file = args()
-        .nth(1)
-        .unwrap_or_else(|| Settings::default_file().to_string());
-    Settings::from_file(&config_file).expect("Config error")
+pub fn load_settings(config_file: Option<String>) -> Settings {
+    let config_file = config_file.unwrap_or_else(|| Settings::default_file().to_string());
+    Settings::from_file(&config_file).unwrap_or_else(|e| panic!("Config error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(names: &[&str]) -> Settings {
+        let default = String::from_utf8_lossy(include_bytes!("default_config.yml"));
+        let mut config = Config::new();
+        config
+            .merge(File::from_str(&default, FileFormat::Yaml))
+            .unwrap();
+        let mut settings: Settings = config.try_into().unwrap();
+        settings.photos.name = names[0].to_string();
+        settings.libraries = names[1..]
+            .iter()
+            .map(|name| Photos {
+                name: name.to_string(),
+                library: format!("~/Pictures/{}.photoslibrary", name),
+                ..settings.photos.clone()
+            })
+            .collect();
+        settings
+    }
+
+    #[test]
+    fn accepts_uniquely_named_libraries() {
+        assert!(settings(&["default"]).validate().is_ok());
+        assert!(settings(&["default", "family", "work"]).validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        assert!(settings(&["default", "default"]).validate().is_err());
+        assert!(settings(&["default", "work", "work"]).validate().is_err());
+    }
+
+    #[test]
+    fn rejects_names_ids_cant_be_qualified_with() {
+        assert!(settings(&["default", "fam:ily"]).validate().is_err());
+        assert!(settings(&["default", ""]).validate().is_err());
+    }
 }
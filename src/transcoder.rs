@@ -58,7 +58,7 @@ impl Job {
 
     fn is_hdr(&self) -> bool {
         let mut probe = Command::new(&self.config.media.ffmpeg.probe);
-        probe.args(&[
+        probe.args([
             "-show_entries",
             "stream=color_space",
             "-select_streams",
@@ -148,8 +148,10 @@ impl Transcoder {
 
         let config = Arc::clone(&self.config);
 
-        w.watch(config.photos.originals_dir(), RecursiveMode::Recursive)
-            .expect("Can't watch originals dir for events");
+        for photos in config.all_libraries() {
+            w.watch(photos.originals_dir(), RecursiveMode::Recursive)
+                .expect("Can't watch originals dir for events");
+        }
 
         let sender = Sender::clone(&self.sender);
 
@@ -174,35 +176,40 @@ impl Transcoder {
     fn scan(&self) {
         let mut transcoded = vec![];
 
-        for entry in WalkDir::new(&self.config.media.videos_path) {
-            if let Ok(v) = entry {
-                let ft = v.file_type();
-                if ft.is_dir() || ft.is_symlink() {
-                    continue;
-                }
+        for v in WalkDir::new(&self.config.media.videos_path)
+            .into_iter()
+            .flatten()
+        {
+            let ft = v.file_type();
+            if ft.is_dir() || ft.is_symlink() {
+                continue;
+            }
 
-                if is_video(v.file_name()) {
-                    let filename = v.file_name().to_owned();
-                    let fnstring = filename.into_string().unwrap();
-                    transcoded.push(fnstring.split(".").next().unwrap().to_owned());
-                }
+            if is_video(v.file_name()) {
+                let filename = v.file_name().to_owned();
+                let fnstring = filename.into_string().unwrap();
+                transcoded.push(fnstring.split(".").next().unwrap().to_owned());
             }
         }
 
-        for entry in WalkDir::new(&self.config.photos.originals_dir()) {
-            if let Ok(v) = entry {
-                let ft = v.file_type();
-                if ft.is_dir() || ft.is_symlink() {
-                    continue;
-                }
+        let originals = self
+            .config
+            .all_libraries()
+            .into_iter()
+            .flat_map(|photos| WalkDir::new(photos.originals_dir()));
 
-                if is_video(v.file_name()) {
-                    let filename = v.file_name().to_owned().into_string().unwrap();
-                    if !transcoded.contains(&filename.split(".").next().unwrap().to_owned()) {
-                        let path = v.path().to_owned();
-                        log::debug!("Sending transcoding job: {:?}", &path);
-                        let _ = self.sender.send(Job::new(path, Arc::clone(&self.config)));
-                    }
+        for v in originals.flatten() {
+            let ft = v.file_type();
+            if ft.is_dir() || ft.is_symlink() {
+                continue;
+            }
+
+            if is_video(v.file_name()) {
+                let filename = v.file_name().to_owned().into_string().unwrap();
+                if !transcoded.contains(&filename.split(".").next().unwrap().to_owned()) {
+                    let path = v.path().to_owned();
+                    log::debug!("Sending transcoding job: {:?}", &path);
+                    let _ = self.sender.send(Job::new(path, Arc::clone(&self.config)));
                 }
             }
         }